@@ -0,0 +1,55 @@
+#!/usr/bin/env bash
+# Check that every register allocator gives the same output as the interpreter and compare
+# them with compare-alloc, that a pinned cell is never spilled, and that shuffling the
+# registers with --alloc-seed doesn't change the output.
+# $@ are the programs to test (default hello.ft and a generated program using many cells).
+# $SEEDS is the amount of seeds to shuffle with (default 30).
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+awk 'BEGIN { srand(3); for (i = 0; i < 2000; i++) { c = 1 + int(rand() * rand() * 12); if (rand() < 0.5) printf "%d`+%d\n", c, int(rand() * 9); else printf "%d`%d\n", c, 1 + int(rand() * 12) } for (c = 1; c <= 12; c++) printf "0`%d\n", c }' > /tmp/alloc.ft
+files=("$@")
+[ ${#files[@]} == 0 ] && files=(hello.ft /tmp/alloc.ft)
+status=0
+for f in "${files[@]}"; do
+	expect=$($bin interpreter "$f" 2>/dev/null)
+	for alloc in fifo lru freq; do
+		if [ "$($bin --alloc $alloc jit2 "$f" 2>/dev/null)" == "$expect" ]; then
+			echo "ok   $f $alloc"
+		else
+			echo "FAIL $f $alloc"
+			status=1
+		fi
+	done
+	$bin compare-alloc "$f" 2>&1 >/dev/null | tail -4
+done
+# Cell 1 is used the most, so pin the second most used cell instead.
+(echo '#!pin 2'; cat /tmp/alloc.ft) > /tmp/pin.ft
+log=$($bin --regs 3 --reg-log jit2 /tmp/pin.ft 2>&1 >/dev/null)
+if ! grep -q "spill cell 2 " <<< "$log" && grep -q "spill cell" <<< "$log"; then
+	echo "ok   #!pin"
+else
+	echo "FAIL #!pin"
+	status=1
+fi
+# Swaps move values between registers, which must not lose track of V or a cell.
+awk 'BEGIN { srand(4); for (i = 0; i < 2000; i++) { c = 1 + int(rand() * 8); r = rand(); if (r < 0.3) printf "~%d`%d\n", c, 1 + int(rand() * 8); else if (r < 0.6) printf "%d`+%d\n", c, int(rand() * 9); else printf "%d`%d\n", c, 1 + int(rand() * 8) } for (c = 1; c <= 8; c++) printf "0`%d\n", c }' > /tmp/swap.ft
+for f in /tmp/alloc.ft /tmp/swap.ft; do
+	expect=$($bin interpreter "$f" 2>/dev/null)
+	failed=0
+	for seed in $(seq 1 "${SEEDS:-30}"); do
+		for alloc in fifo lru freq; do
+			for regs in 2 5; do
+				args=(--alloc $alloc --regs $regs --alloc-seed "$seed")
+				if [ "$($bin "${args[@]}" jit2 "$f" 2>/dev/null)" != "$expect" ]; then
+					echo "FAIL $f ${args[*]}"
+					failed=1
+				fi
+			done
+		done
+	done
+	if [ $failed == 0 ]; then
+		echo "ok   $f ${SEEDS:-30} allocation seeds"
+	fi
+	status=$((status | failed))
+done
+exit $status
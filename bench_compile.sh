@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+# Measure how JIT compile time scales with program size.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+printf '%8s %12s %12s\n' ops jit jit2
+for n in 100 1000 10000 100000; do
+	$bin gen $n > /tmp/compile_$n.ft || exit $?
+	a=$($bin --compile-only jit /tmp/compile_$n.ft 2>&1) || exit $?
+	b=$($bin --compile-only jit2 /tmp/compile_$n.ft 2>&1) || exit $?
+	printf '%8s %12s %12s\n' $n "$a" "$b"
+done
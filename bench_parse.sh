@@ -0,0 +1,9 @@
+#!/usr/bin/env bash
+# Measure parse throughput on large generated programs.
+# $1 is the amount of ops per program (default 1000000).
+n=${1:-1000000}
+cargo b --release || exit $?
+awk -v n=$n 'BEGIN { for (i = 0; i < n; i++) printf "%d`+%d\n", i % 64, i % 7 - 3 }' > /tmp/parse_small.ft
+awk -v n=$n 'BEGIN { srand(1); for (i = 0; i < n; i++) printf "%d`%d\n+%d`+-%d\n", int(rand() * 1e9), int(rand() * 1e18), int(rand() * 1e9), int(rand() * 1e18) }' > /tmp/parse_ints.ft
+./target/release/dynasm_experiments bench-parse /tmp/parse_small.ft || exit $?
+./target/release/dynasm_experiments bench-parse /tmp/parse_ints.ft || exit $?
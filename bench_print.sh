@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+# Measure how much printing costs in each backend with a program that writes cell 0 a
+# million times through nested calls, once as is and once with --no-print.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+# Op 0 to 99 call the routine at op 101 which calls the routine at op 202 100 times, which
+# writes cell 0 100 times.
+{
+	for i in $(seq 0 99); do echo ">$((101 - i))"; done
+	echo "<"
+	for i in $(seq 101 200); do echo ">$((202 - i))"; done
+	echo "<"
+	for i in $(seq 202 301); do echo "0\`+1"; done
+	echo "<"
+} > /tmp/print.ft
+prints=1000000
+for mode in interpreter jit jit2; do
+	with=$($bin --time-unit ns --output byte $mode /tmp/print.ft 2>&1 >/dev/null)
+	without=$($bin --time-unit ns --output byte --no-print $mode /tmp/print.ft 2>&1 >/dev/null)
+	per=$(awk "BEGIN { printf \"%.2f\", ($with - $without) / $prints }")
+	echo "$mode: $with ns with printing, $without ns without, $per ns per print"
+done
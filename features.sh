@@ -0,0 +1,17 @@
+#!/usr/bin/env bash
+# Check that the crate builds, passes clippy and its doctests without the jit feature, which
+# is how embedders that only want the interpreter use it, and that dynasmrt isn't pulled in.
+status=0
+check() {
+	if "${@:2}" > /dev/null 2>&1; then
+		echo "ok   $1"
+	else
+		echo "FAIL $1"
+		status=1
+	fi
+}
+check build cargo build -q --no-default-features
+check clippy cargo clippy -q --all-targets --no-default-features -- -D warnings
+check test cargo test -q --no-default-features
+check "no dynasmrt" bash -c '! cargo tree -q --no-default-features -e normal | grep -q dynasmrt'
+exit $status
@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Check that flags which can't be combined are rejected with an error, and that the same
+# program runs without the conflicting flag.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+status=0
+printf '1`+9223372036854775807 1`+1 1`+-1 0`1\n' > /tmp/flags.ft
+# Passes fold additions as if cells wrap around, which changes saturating cells.
+for flags in "-O1" "-O2" "--pass fold"; do
+	out=$($bin --cell saturating $flags interpreter /tmp/flags.ft 2>&1 >/dev/null)
+	if [ $? == 1 ] && [[ "$out" == *"--cell saturating can't be combined"* ]]; then
+		echo "ok   --cell saturating $flags"
+	else
+		echo "FAIL --cell saturating $flags: $out"
+		status=1
+	fi
+done
+out=$($bin --cell saturating --output decimal -O0 interpreter /tmp/flags.ft 2>/dev/null)
+if [ "$out" == 9223372036854775806 ]; then
+	echo "ok   --cell saturating -O0"
+else
+	echo "FAIL --cell saturating -O0: $out"
+	status=1
+fi
+exit $status
@@ -0,0 +1,16 @@
+#!/usr/bin/env bash
+# Run the modes that check random programs, with fixed seeds so that a failure can be
+# reproduced with the command that is printed.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+status=0
+for args in "--seed 1 fuzz-parse 20000" "--seed 1 check-passes 12000"; do
+	if out=$(timeout 120 $bin $args 2>&1); then
+		echo "ok   $args"
+	else
+		echo "FAIL $args"
+		echo "$out" | tail -n 5
+		status=1
+	fi
+done
+exit $status
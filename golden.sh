@@ -0,0 +1,24 @@
+#!/usr/bin/env bash
+# Run each example through the checked interpreter and compare its output with the matching
+# .out file. Input is read from the matching .in file and flags from the matching .args file,
+# if any. With UPDATE_GOLDEN=1 the .out files are written instead.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+status=0
+for f in examples/*.ft; do
+	golden=${f%.ft}.out
+	input=${f%.ft}.in
+	[ -f "$input" ] || input=/dev/null
+	args=$(cat "${f%.ft}.args" 2>/dev/null)
+	if [ "$UPDATE_GOLDEN" == 1 ]; then
+		$bin $args checked "$f" < "$input" > "$golden" 2>/dev/null || exit $?
+		echo "updated $golden"
+	elif $bin $args checked "$f" < "$input" 2>/dev/null | cmp -s - "$golden"; then
+		echo "ok   $f"
+	else
+		echo "FAIL $f"
+		$bin $args checked "$f" < "$input" 2>/dev/null | diff "$golden" - | head -n 20
+		status=1
+	fi
+done
+exit $status
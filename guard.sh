@@ -0,0 +1,18 @@
+#!/usr/bin/env bash
+# Check that compiled programs accessing cells beyond the tape are caught with --guard-pages.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+status=0
+for cell in 16 -600; do
+	printf '#!tape 16\n0`+65\n%d`+1\n' $cell > /tmp/guard.ft
+	for mode in jit jit2; do
+		out=$($bin --guard-pages $mode /tmp/guard.ft 2>&1 >/dev/null)
+		if [ $? == 1 ] && [[ "$out" == *"cell $cell outside the tape"* ]]; then
+			echo "ok   $mode cell $cell"
+		else
+			echo "FAIL $mode cell $cell: $out"
+			status=1
+		fi
+	done
+done
+exit $status
@@ -0,0 +1,18 @@
+#!/usr/bin/env bash
+# Hand each example that doesn't read input off from the checked interpreter to both JITs,
+# which fails if they leave other output or another tape than the interpreter alone.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+status=0
+for f in examples/*.ft; do
+	[ -f "${f%.ft}.in" ] && continue
+	args=$(cat "${f%.ft}.args" 2>/dev/null)
+	if out=$(timeout 10 $bin $args handoff "$f" 2>&1 >/dev/null); then
+		echo "ok   $f"
+	else
+		echo "FAIL $f"
+		echo "$out" | tail -n 3
+		status=1
+	fi
+done
+exit $status
@@ -0,0 +1,74 @@
+#!/usr/bin/env bash
+# Link programs compiled to object files into a C program and compare their output with
+# the interpreter's, both with the bundled print and with one provided by the C program.
+# Then do the same with hello.ft and examples/calls.ft compiled into one object and called
+# through their own symbols.
+# $@ are the programs to test (default hello.ft).
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+cat > /tmp/object_main.c << 'C'
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+void run_program(intptr_t *tape);
+#ifdef EXTERNAL
+void print(intptr_t v) { putchar(v < 0x80 ? v : '?'); }
+#endif
+int main() {
+	intptr_t *tape = calloc(0x10000, sizeof(*tape));
+	run_program(tape);
+	fflush(stdout);
+	return 0;
+}
+C
+status=0
+for f in "${@:-hello.ft}"; do
+	expect=$($bin interpreter "$f" 2>/dev/null)
+	for mode in jit jit2; do
+		for runtime in --bundle-runtime ""; do
+			$bin $runtime --emit-object /tmp/object.o $mode "$f" || exit $?
+			define=$([ -z "$runtime" ] && echo -DEXTERNAL)
+			cc $define /tmp/object_main.c /tmp/object.o -o /tmp/object || exit $?
+			if [ "$(/tmp/object)" == "$expect" ]; then
+				echo "ok   $f $mode ${runtime:---external-print}"
+			else
+				echo "FAIL $f $mode ${runtime:---external-print}"
+				status=1
+			fi
+		done
+	done
+done
+
+cat > /tmp/object_both.c << 'C'
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+void run_hello(intptr_t *tape);
+void run_calls(intptr_t *tape);
+#ifdef EXTERNAL
+void print(intptr_t v) { putchar(v < 0x80 ? v : '?'); }
+#endif
+int main() {
+	intptr_t *tape = calloc(0x10000, sizeof(*tape));
+	run_calls(tape);
+	tape = calloc(0x10000, sizeof(*tape));
+	run_hello(tape);
+	fflush(stdout);
+	return 0;
+}
+C
+expect=$($bin interpreter examples/calls.ft 2>/dev/null; $bin interpreter hello.ft 2>/dev/null)
+for mode in jit jit2; do
+	for runtime in --bundle-runtime ""; do
+		$bin $runtime --emit-object /tmp/object.o $mode hello.ft examples/calls.ft || exit $?
+		define=$([ -z "$runtime" ] && echo -DEXTERNAL)
+		cc $define /tmp/object_both.c /tmp/object.o -o /tmp/object || exit $?
+		if [ "$(/tmp/object)" == "$expect" ]; then
+			echo "ok   hello.ft and calls.ft $mode ${runtime:---external-print}"
+		else
+			echo "FAIL hello.ft and calls.ft $mode ${runtime:---external-print}"
+			status=1
+		fi
+	done
+done
+exit $status
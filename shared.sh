@@ -0,0 +1,54 @@
+#!/usr/bin/env bash
+# Load programs compiled to shared libraries with dlopen from C, and with ctypes as in the
+# README if Python is installed, and compare the output of run_program with the interpreter's.
+# $@ are the programs to test (default hello.ft and examples/calls.ft).
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+cat > /tmp/shared_main.c << 'C'
+#include <dlfcn.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+int main(int argc, char **argv) {
+	void *lib = dlopen(argv[1], RTLD_NOW);
+	if (!lib) {
+		fprintf(stderr, "%s\n", dlerror());
+		return 1;
+	}
+	void (*run_program)(intptr_t *) = (void (*)(intptr_t *))dlsym(lib, "run_program");
+	if (!run_program) {
+		fprintf(stderr, "%s\n", dlerror());
+		return 1;
+	}
+	run_program(calloc(0x10000, sizeof(intptr_t)));
+	return 0;
+}
+C
+cc /tmp/shared_main.c -o /tmp/shared_main -ldl || exit $?
+status=0
+loaders=(/tmp/shared_main)
+if command -v python3 > /dev/null; then
+	cat > /tmp/shared_main.py << 'PY'
+import ctypes, sys
+tape = (ctypes.c_ssize_t * 0x10000)()
+ctypes.CDLL(sys.argv[1]).run_program(tape)
+PY
+	loaders+=("python3 /tmp/shared_main.py")
+fi
+files=("$@")
+[ $# == 0 ] && files=(hello.ft examples/calls.ft)
+for f in "${files[@]}"; do
+	expect=$($bin interpreter "$f" 2>/dev/null)
+	for mode in jit jit2; do
+		$bin --emit-shared /tmp/shared.so $mode "$f" || exit $?
+		for loader in "${loaders[@]}"; do
+			if [ "$($loader /tmp/shared.so)" == "$expect" ]; then
+				echo "ok   $f $mode ${loader##*/}"
+			else
+				echo "FAIL $f $mode ${loader##*/}"
+				status=1
+			fi
+		done
+	done
+done
+exit $status
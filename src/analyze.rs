@@ -0,0 +1,320 @@
+//! Static analysis of programs.
+
+use crate::Op;
+#[cfg(feature = "jit")]
+use crate::{CompileError, JitProgram};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Split ops into basic blocks, which are only entered at the first op and only left at the
+/// last op.
+///
+/// The targets of [`Op::JmpA`] aren't known, so the blocks are only correct for programs
+/// without them.
+pub fn basic_blocks(ops: &[Op], entry: usize) -> Vec<Range<usize>> {
+	let mut leaders = vec![false; ops.len() + 1];
+	leaders[0] = true;
+	leaders[ops.len()] = true;
+	if let Some(l) = leaders.get_mut(entry) {
+		*l = true;
+	}
+	for (i, op) in ops.iter().enumerate() {
+		match *op {
+			Op::JmpV(_, b) => {
+				if let Some(l) = i.checked_add_signed(b).and_then(|t| leaders.get_mut(t)) {
+					*l = true;
+				}
+				leaders[i + 1] = true;
+			}
+			Op::Call(b) => {
+				if let Some(l) = i.checked_add_signed(b).and_then(|t| leaders.get_mut(t)) {
+					*l = true;
+				}
+				leaders[i + 1] = true;
+			}
+			Op::JmpA(..) | Op::Ret => leaders[i + 1] = true,
+			Op::SetV(..) | Op::SetA(..) | Op::ReadInts(_) | Op::Swap(..) => (),
+		}
+	}
+	let mut blocks = Vec::new();
+	let mut start = 0;
+	for (i, _) in leaders.iter().enumerate().skip(1).filter(|(_, l)| **l) {
+		blocks.push(start..i);
+		start = i;
+	}
+	blocks
+}
+
+/// The ops of each loop, from the target of a backward `+A`+B` up to and including the jump.
+///
+/// ```
+/// use dynasm_experiments::{analyze::loops, parse};
+///
+/// let (ops, _) = parse(b"1`+1 2`+1 +0`+-1 0`+1 +0`+-4".to_vec());
+/// assert_eq!(loops(&ops), [1..3, 0..5]);
+/// ```
+pub fn loops(ops: &[Op]) -> Vec<Range<usize>> {
+	let mut loops = Vec::new();
+	for (i, op) in ops.iter().enumerate() {
+		if let Op::JmpV(_, b @ ..=0) = *op {
+			loops.extend(i.checked_add_signed(b).map(|t| t..i + 1));
+		}
+	}
+	loops
+}
+
+/// The cells an op reads and the cells it writes.
+fn accesses(op: Op) -> ([Option<isize>; 2], [Option<isize>; 2]) {
+	match op {
+		Op::SetV(a, _) => ([Some(a), None], [Some(a), None]),
+		Op::SetA(a, b) => ([Some(a), Some(b)], [Some(a), None]),
+		Op::Swap(a, b) => ([Some(a), Some(b)], [Some(a), Some(b)]),
+		Op::JmpV(..) | Op::Call(_) | Op::Ret | Op::ReadInts(_) => ([None, None], [None, None]),
+		Op::JmpA(_, b) => ([Some(b), None], [None, None]),
+	}
+}
+
+/// Whether an op sets V.
+fn sets_v(op: Op) -> bool {
+	matches!(op, Op::SetV(..) | Op::SetA(..) | Op::ReadInts(_))
+}
+
+/// Whether an op reads V.
+fn reads_v(op: Op) -> bool {
+	matches!(op, Op::JmpV(..) | Op::JmpA(..))
+}
+
+/// The data dependence graph of straight-line ops, as the indices of the earlier ops each op
+/// depends on.
+///
+/// An op depends on another if one writes a cell the other accesses, or if it is a jump and
+/// the other op sets V. Reading input may write any cell, so it depends on every earlier op
+/// and every later op depends on it.
+pub fn dependencies(ops: &[Op]) -> Vec<Vec<usize>> {
+	ops.iter()
+		.enumerate()
+		.map(|(i, &op)| {
+			let (reads, writes) = accesses(op);
+			let reads_v = reads_v(op);
+			let overlap = |x: [Option<isize>; 2], y: [Option<isize>; 2]| {
+				x.iter().flatten().any(|&c| y.contains(&Some(c)))
+			};
+			(0..i)
+				.filter(|&j| {
+					let (r, w) = accesses(ops[j]);
+					let raw = overlap(w, reads);
+					let war = overlap(writes, r);
+					let waw = overlap(writes, w);
+					let input = matches!(op, Op::ReadInts(_)) || matches!(ops[j], Op::ReadInts(_));
+					raw || war || waw || input || (reads_v && sets_v(ops[j]))
+				})
+				.collect()
+		})
+		.collect()
+}
+
+/// The length of the longest chain of straight-line ops where each depends on the previous.
+///
+/// ```
+/// use dynasm_experiments::{analyze::critical_path, parse};
+///
+/// // The first three ops are independent and the last only depends on the first two.
+/// let (ops, _) = parse(b"1`+1 2`+1 3`+1 1`2".to_vec());
+/// assert_eq!(critical_path(&ops), 2);
+/// ```
+pub fn critical_path(ops: &[Op]) -> usize {
+	let mut depth = Vec::<usize>::with_capacity(ops.len());
+	for deps in dependencies(ops) {
+		depth.push(1 + deps.iter().map(|&j| depth[j]).max().unwrap_or(0));
+	}
+	depth.into_iter().max().unwrap_or(0)
+}
+
+/// Print an analysis of each basic block.
+pub fn report(ops: &[Op], entry: usize) {
+	if ops.iter().any(|op| matches!(op, Op::JmpA(..))) {
+		println!("warning: targets of +A`B are not known, blocks may be entered elsewhere");
+	}
+	for block in basic_blocks(ops, entry) {
+		let ops = &ops[block.clone()];
+		let path = critical_path(ops);
+		let ilp = ops.len() as f64 / path as f64;
+		println!(
+			"block {:?}: {} ops, critical path {} ({:.2} ops per step)",
+			block,
+			ops.len(),
+			path,
+			ilp
+		);
+	}
+}
+
+/// The amount of ops of each kind and the bytes of code they were compiled to, given the
+/// offset of each op in the code followed by the end of the last op.
+///
+/// Kinds are in the order they first appear in.
+///
+/// ```
+/// use dynasm_experiments::{analyze::code_sizes, parse};
+///
+/// let (ops, _) = parse(b"1`+1 2`1 3`+1 <".to_vec());
+/// let sizes = code_sizes(&ops, &[0, 10, 24, 30, 34]);
+/// assert_eq!(sizes, [("SetV", 2, 16), ("SetA", 1, 14), ("Ret", 1, 4)]);
+/// ```
+pub fn code_sizes(ops: &[Op], offsets: &[usize]) -> Vec<(&'static str, usize, usize)> {
+	let mut sizes = Vec::<(&str, usize, usize)>::new();
+	for (op, o) in ops.iter().zip(offsets.windows(2)) {
+		let size = o[1] - o[0];
+		match sizes.iter_mut().find(|(name, ..)| *name == op.name()) {
+			Some((_, count, bytes)) => {
+				*count += 1;
+				*bytes += size;
+			}
+			None => sizes.push((op.name(), 1, size)),
+		}
+	}
+	sizes
+}
+
+/// The longest backward and forward distance in bytes of branches given as the offset right
+/// after each and the offset it goes to, along with the amount of branches whose displacement
+/// fits in 8 bits, which a short encoding could have used.
+///
+/// The distances are 0 if there are no branches in that direction.
+///
+/// ```
+/// use dynasm_experiments::analyze::branch_distances;
+///
+/// let distances = branch_distances(&[(40, 10), (50, 300), (60, 60), (200, 20)]);
+/// assert_eq!(distances, (180, 250, 2));
+/// ```
+pub fn branch_distances(branches: &[(usize, usize)]) -> (usize, usize, usize) {
+	let backward = branches.iter().map(|&(at, t)| at.saturating_sub(t));
+	let forward = branches.iter().map(|&(at, t)| t.saturating_sub(at));
+	let short = branches.iter().filter(|&&(at, t)| {
+		let d = t as isize - at as isize;
+		i8::try_from(d).is_ok()
+	});
+	(
+		backward.max().unwrap_or(0),
+		forward.max().unwrap_or(0),
+		short.count(),
+	)
+}
+
+/// Size of a typical L1 instruction cache in bytes.
+pub const L1I_SIZE: usize = 32 * 1024;
+
+/// Print the size of the compiled code, of each kind of op and of each loop in it, how far
+/// its branches go, and warn about loops that don't fit in the L1 instruction cache.
+///
+/// The interpreter's dispatch loop always fits, so a loop that doesn't may run faster
+/// interpreted.
+#[cfg(feature = "jit")]
+pub fn report_code(ops: &[Op], programs: &[(&str, Result<JitProgram, CompileError>)]) {
+	for (name, program) in programs {
+		let program = match program {
+			Ok(p) => p,
+			Err(e) => {
+				println!("{}: {}", name, e);
+				continue;
+			}
+		};
+		let size = program.code().len();
+		let share = size as f64 / L1I_SIZE as f64 * 100.0;
+		println!("{}: {} bytes of code ({:.1}% of L1i)", name, size, share);
+		let offsets = program.op_offsets();
+		println!(
+			"{}: {:<8} {:>8} {:>10} {:>8}",
+			name, "op", "count", "bytes", "average"
+		);
+		for (kind, count, bytes) in code_sizes(ops, offsets) {
+			let average = bytes as f64 / count as f64;
+			println!(
+				"{}: {:<8} {:>8} {:>10} {:>8.1}",
+				name, kind, count, bytes, average
+			);
+		}
+		let branches = program.branches();
+		let (backward, forward, short) = branch_distances(branches);
+		println!(
+			"{}: {} branches, longest backward {} bytes, longest forward {} bytes, {} within rel8",
+			name,
+			branches.len(),
+			backward,
+			forward,
+			short
+		);
+		for l in loops(ops) {
+			let size = offsets[l.end] - offsets[l.start];
+			println!("{}: loop {:?}: {} bytes", name, l, size);
+			if size > L1I_SIZE {
+				println!("warning: {} loop {:?} does not fit in L1i", name, l);
+			}
+		}
+	}
+}
+
+/// Render which routines call which as a graph in the DOT language.
+///
+/// A routine starts at the entry or at the target of a call and consists of the ops that are
+/// reachable from there without calling. Routines with a `+A`B`, which may continue anywhere,
+/// get a dashed edge to a `dynamic` node.
+///
+/// ```
+/// use dynasm_experiments::{analyze::callgraph, parse};
+///
+/// // The routines at ops 2 and 5 call each other.
+/// let (ops, _) = parse(b">2 < 1`+1 >2 < +0`+2 < >-5 <".to_vec());
+/// let dot = callgraph(&ops, 0);
+/// assert!(dot.contains("op0 -> op2;"));
+/// assert!(dot.contains("op2 -> op5;"));
+/// assert!(dot.contains("op5 -> op2;"));
+/// ```
+pub fn callgraph(ops: &[Op], entry: usize) -> String {
+	let mut routines = BTreeSet::from([entry]);
+	let mut todo = vec![entry];
+	let mut out = String::from("digraph calls {\n");
+	writeln!(out, "\top{} [label=\"entry (op {})\"];", entry, entry).unwrap();
+	while let Some(start) = todo.pop() {
+		let mut seen = vec![false; ops.len()];
+		let mut next = vec![start];
+		let (mut callees, mut dynamic) = (BTreeSet::new(), false);
+		while let Some(i) = next.pop() {
+			let Some(s) = seen.get_mut(i).filter(|s| !**s) else {
+				continue;
+			};
+			*s = true;
+			let mut jump = |b: isize| next.extend(i.checked_add_signed(b));
+			match ops[i] {
+				Op::SetV(..) | Op::SetA(..) | Op::ReadInts(_) | Op::Swap(..) => jump(1),
+				Op::JmpV(_, b) => {
+					jump(1);
+					jump(b);
+				}
+				Op::JmpA(..) => {
+					jump(1);
+					dynamic = true;
+				}
+				Op::Call(b) => {
+					jump(1);
+					callees.extend(i.checked_add_signed(b).filter(|&t| t < ops.len()));
+				}
+				Op::Ret => (),
+			}
+		}
+		for c in callees {
+			if routines.insert(c) {
+				writeln!(out, "\top{} [label=\"op {}\"];", c, c).unwrap();
+				todo.push(c);
+			}
+			writeln!(out, "\top{} -> op{};", start, c).unwrap();
+		}
+		if dynamic {
+			writeln!(out, "\top{} -> dynamic [style=dashed];", start).unwrap();
+		}
+	}
+	out.push_str("}\n");
+	out
+}
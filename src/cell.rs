@@ -0,0 +1,77 @@
+//! Types the interpreter can use for tape cells.
+
+use std::io::Write;
+use std::num::Saturating;
+
+/// Arithmetic on tape cells for [`run_with`](crate::run_with).
+///
+/// ```
+/// use dynasm_experiments::cell::Cell;
+/// use std::num::Saturating;
+///
+/// assert_eq!(i32::MAX.add(1), i32::MIN);
+/// assert_eq!(u64::from_isize(-1), u64::MAX);
+/// assert_eq!(Saturating(i64::MAX).add(Saturating(1)), Saturating(i64::MAX));
+///
+/// let mut out = Vec::new();
+/// (-1i32).fmt_decimal(&mut out);
+/// u64::MAX.fmt_decimal(&mut out);
+/// assert_eq!(out, b"-1\n18446744073709551615\n");
+/// ```
+pub trait Cell: Copy + PartialEq + Default {
+	/// Convert a constant of an op, wrapping around if it doesn't fit.
+	fn from_isize(n: isize) -> Self;
+
+	/// The value as it is printed or used as a jump distance.
+	fn to_isize(self) -> isize;
+
+	/// Add a value to the cell, with overflow as the type defines it.
+	fn add(self, other: Self) -> Self;
+
+	/// Append the value in decimal and a newline, as [`Output::Decimal`](crate::Output)
+	/// prints it. Unlike [`Cell::to_isize`], this keeps unsigned values unsigned.
+	fn fmt_decimal(self, out: &mut Vec<u8>);
+}
+
+macro_rules! wrapping {
+	($($t:ty)*) => {
+		$(impl Cell for $t {
+			fn from_isize(n: isize) -> Self {
+				n as $t
+			}
+
+			fn to_isize(self) -> isize {
+				self as isize
+			}
+
+			fn add(self, other: Self) -> Self {
+				self.wrapping_add(other)
+			}
+
+			fn fmt_decimal(self, out: &mut Vec<u8>) {
+				writeln!(out, "{}", self).unwrap();
+			}
+		})*
+	};
+}
+
+wrapping!(isize i64 i32 u64);
+
+/// Additions clamp to the range of the type instead of wrapping around.
+impl Cell for Saturating<i64> {
+	fn from_isize(n: isize) -> Self {
+		Saturating(n as i64)
+	}
+
+	fn to_isize(self) -> isize {
+		self.0 as isize
+	}
+
+	fn add(self, other: Self) -> Self {
+		self + other
+	}
+
+	fn fmt_decimal(self, out: &mut Vec<u8>) {
+		writeln!(out, "{}", self.0).unwrap();
+	}
+}
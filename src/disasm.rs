@@ -0,0 +1,155 @@
+//! Disassembly of generated machine code with `objdump`.
+
+use crate::Op;
+use std::fmt;
+use std::io;
+use std::process::Command;
+use std::str::FromStr;
+
+/// The assembly syntax to disassemble to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+	/// Intel syntax, as used by dynasm.
+	Intel,
+	/// AT&T syntax, as used by default by gdb and objdump.
+	Att,
+}
+
+impl Syntax {
+	pub const ALL: [Self; 2] = [Self::Intel, Self::Att];
+}
+
+impl fmt::Display for Syntax {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Intel => "intel",
+			Self::Att => "att",
+		})
+	}
+}
+
+impl FromStr for Syntax {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|x| x.to_string() == s)
+			.ok_or("syntax must be intel or att")
+	}
+}
+
+/// Disassemble x86-64 machine code, one instruction per line with its offset and bytes.
+///
+/// This requires `objdump` from GNU binutils.
+///
+/// ```
+/// use dynasm_experiments::disasm::{disassemble, Syntax};
+///
+/// // mov rax, [rbx + 8]
+/// let code = [0x48, 0x8b, 0x43, 0x08];
+/// let intel = disassemble(&code, Syntax::Intel).unwrap();
+/// let att = disassemble(&code, Syntax::Att).unwrap();
+/// assert!(intel.contains("rax,QWORD PTR [rbx+0x8]"));
+/// assert!(att.contains("0x8(%rbx),%rax"));
+/// ```
+pub fn disassemble(code: &[u8], syntax: Syntax) -> io::Result<String> {
+	disassemble_at(code, 0, syntax)
+}
+
+/// Disassemble like [`disassemble`], with offsets as if the code started at `start`.
+///
+/// ```
+/// use dynasm_experiments::disasm::{disassemble_at, Syntax};
+///
+/// // ret
+/// let listing = disassemble_at(&[0xc3], 0x40, Syntax::Intel).unwrap();
+/// assert!(listing.trim_start().starts_with("40:"));
+/// ```
+pub fn disassemble_at(code: &[u8], start: usize, syntax: Syntax) -> io::Result<String> {
+	let path = std::env::temp_dir().join(format!("dynasm_experiments_{}.bin", std::process::id()));
+	std::fs::write(&path, code)?;
+	let out = Command::new("objdump")
+		.args(["-D", "-b", "binary", "-m", "i386:x86-64", "-M"])
+		.arg(syntax.to_string())
+		.arg(format!("--adjust-vma={:#x}", start))
+		.arg(&path)
+		.output();
+	std::fs::remove_file(&path)?;
+	let out = out?;
+	if !out.status.success() {
+		let e = String::from_utf8_lossy(&out.stderr).into_owned();
+		return Err(io::Error::other(e));
+	}
+	// Skip the headers before the first instruction.
+	let out = String::from_utf8_lossy(&out.stdout);
+	let lines = out.lines().skip_while(|l| !l.ends_with("<.data>:")).skip(1);
+	Ok(lines.map(|l| l.to_string() + "\n").collect())
+}
+
+/// Disassemble the code of `ops` into a listing that stays the same where the code does, for
+/// comparing the code of similar programs.
+///
+/// `offsets` are where each op starts followed by the end of the last op. The listing has a
+/// heading for each op and leaves out offsets and bytes. Branch targets are given as the op
+/// they are in and the offset into it, so code that moves elsewhere still lists the same.
+///
+/// ```
+/// use dynasm_experiments::{disasm::{listing, Syntax}, parse};
+///
+/// let (ops, _) = parse(b"1`+1 <".to_vec());
+/// // jmp to the ret, ret
+/// let code = [0xeb, 0x00, 0xc3];
+/// let listing = listing(&code, &ops, &[0, 2, 3], Syntax::Intel).unwrap();
+/// assert_eq!(listing, "# op 0: 1`+1\njmp op 1\n# op 1: <\nret\n# end\n");
+/// ```
+pub fn listing(code: &[u8], ops: &[Op], offsets: &[usize], syntax: Syntax) -> io::Result<String> {
+	let name = |at: usize| {
+		let i = offsets.partition_point(|&o| o <= at);
+		let (what, start) = match i.checked_sub(1) {
+			None => ("start".to_string(), 0),
+			Some(i) if i >= ops.len() => ("end".to_string(), offsets[i]),
+			Some(i) => (format!("op {}", i), offsets[i]),
+		};
+		match at - start {
+			0 => what,
+			d => format!("{}+{:#x}", what, d),
+		}
+	};
+	let (mut out, mut next) = (String::new(), 0);
+	// Headings of the ops that start at or before `at`.
+	let mut headings = |out: &mut String, at: usize| {
+		while offsets.get(next).is_some_and(|&o| o <= at) {
+			match ops.get(next) {
+				Some(op) => out.push_str(&format!("# op {}: {}\n", next, op)),
+				None => out.push_str("# end\n"),
+			}
+			next += 1;
+		}
+	};
+	for line in disassemble(code, syntax)?.lines() {
+		let mut fields = line.split('\t');
+		let at = fields.next().and_then(|a| a.trim().strip_suffix(':'));
+		let at = at.and_then(|a| usize::from_str_radix(a, 16).ok());
+		let (Some(at), Some(insn)) = (at, fields.nth(1)) else {
+			continue;
+		};
+		headings(&mut out, at);
+		// Offsets in comments change with the code before them.
+		let insn = insn.split('#').next().unwrap_or_default();
+		let mut words = insn.split_whitespace().collect::<Vec<_>>();
+		let target = match words[..] {
+			[m, t] if m.starts_with('j') || m.starts_with("call") => t.strip_prefix("0x"),
+			_ => None,
+		};
+		let target = target.and_then(|t| usize::from_str_radix(t, 16).ok());
+		let target = target.map(name);
+		if let Some(t) = &target {
+			words[1] = t;
+		}
+		out.push_str(&words.join(" "));
+		out.push('\n');
+	}
+	headings(&mut out, code.len());
+	Ok(out)
+}
@@ -0,0 +1,494 @@
+//! Minimal ELF writer for ahead-of-time compiled programs.
+//!
+//! Only x86-64 little-endian files are produced.
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const DYN_SIZE: usize = 16;
+const PAGE_SIZE: usize = 0x1000;
+
+const RELA_SIZE: usize = 24;
+
+const ET_REL: u16 = 1;
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_HASH: u32 = 5;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_DYNSYM: u32 = 11;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+
+const R_X86_64_PLT32: u64 = 4;
+
+const DT_NULL: u64 = 0;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SYMENT: u64 = 11;
+
+fn align(n: usize, to: usize) -> usize {
+	(n + to - 1) & !(to - 1)
+}
+
+fn pad(out: &mut Vec<u8>, to: usize) {
+	out.resize(to, 0);
+}
+
+fn u16(out: &mut Vec<u8>, v: u16) {
+	out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn u32(out: &mut Vec<u8>, v: u32) {
+	out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn u64(out: &mut Vec<u8>, v: u64) {
+	out.extend_from_slice(&v.to_le_bytes());
+}
+
+/// A string table with the offset of each added string.
+struct StrTab(Vec<u8>);
+
+impl StrTab {
+	fn new() -> Self {
+		Self(vec![0])
+	}
+
+	fn add(&mut self, s: &str) -> u32 {
+		let i = self.0.len();
+		self.0.extend_from_slice(s.as_bytes());
+		self.0.push(0);
+		i as u32
+	}
+}
+
+fn header(out: &mut Vec<u8>, kind: u16, phnum: usize, shoff: usize, shnum: usize, shstrndx: usize) {
+	out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+	pad(out, 16);
+	u16(out, kind);
+	u16(out, EM_X86_64);
+	u32(out, 1);
+	u64(out, 0);
+	u64(out, if phnum > 0 { EHDR_SIZE as u64 } else { 0 });
+	u64(out, shoff as u64);
+	u32(out, 0);
+	u16(out, EHDR_SIZE as u16);
+	u16(out, if phnum > 0 { PHDR_SIZE as u16 } else { 0 });
+	u16(out, phnum as u16);
+	u16(out, SHDR_SIZE as u16);
+	u16(out, shnum as u16);
+	u16(out, shstrndx as u16);
+}
+
+fn program_header(
+	out: &mut Vec<u8>,
+	kind: u32,
+	flags: u32,
+	offset: usize,
+	size: usize,
+	align: usize,
+) {
+	u32(out, kind);
+	u32(out, flags);
+	u64(out, offset as u64);
+	u64(out, offset as u64);
+	u64(out, offset as u64);
+	u64(out, size as u64);
+	u64(out, size as u64);
+	u64(out, align as u64);
+}
+
+/// A section header. `addr` is only set for sections that are loaded.
+struct Section {
+	name: u32,
+	kind: u32,
+	flags: u64,
+	addr: usize,
+	offset: usize,
+	size: usize,
+	link: u32,
+	info: u32,
+	align: usize,
+	entsize: usize,
+}
+
+impl Section {
+	fn write(&self, out: &mut Vec<u8>) {
+		u32(out, self.name);
+		u32(out, self.kind);
+		u64(out, self.flags);
+		u64(out, self.addr as u64);
+		u64(out, self.offset as u64);
+		u64(out, self.size as u64);
+		u32(out, self.link);
+		u32(out, self.info);
+		u64(out, self.align as u64);
+		u64(out, self.entsize as u64);
+	}
+}
+
+fn symbol(out: &mut Vec<u8>, name: u32, info: u8, shndx: u16, value: usize) {
+	u32(out, name);
+	out.push(info);
+	out.push(0);
+	u16(out, shndx);
+	u64(out, value as u64);
+	u64(out, 0);
+}
+
+/// Create a shared object containing `code` and exporting `symbols` as functions.
+///
+/// Each symbol is a name with an offset into `code`. The code must be position independent
+/// and may not depend on any other library.
+pub fn shared_object(code: &[u8], symbols: &[(&str, usize)]) -> Vec<u8> {
+	const PHNUM: usize = 4;
+	const TEXT_INDEX: u16 = 4;
+
+	let mut dynstr = StrTab::new();
+	let names = symbols
+		.iter()
+		.map(|(s, _)| dynstr.add(s))
+		.collect::<Vec<_>>();
+	let nsym = symbols.len() + 1;
+
+	// A single bucket with every symbol chained to the previous one.
+	let hash = [1, nsym as u32, nsym as u32 - 1]
+		.into_iter()
+		.chain([0].into_iter().chain(0..nsym as u32 - 1))
+		.collect::<Vec<_>>();
+
+	let hash_off = align(EHDR_SIZE + PHNUM * PHDR_SIZE, 8);
+	let dynsym_off = align(hash_off + hash.len() * 4, 8);
+	let dynstr_off = dynsym_off + nsym * SYM_SIZE;
+	let text_off = align(dynstr_off + dynstr.0.len(), 16);
+	let text_end = text_off + code.len();
+	let dynamic_off = align(text_end, PAGE_SIZE);
+	let dynamic = [
+		(DT_HASH, hash_off),
+		(DT_STRTAB, dynstr_off),
+		(DT_SYMTAB, dynsym_off),
+		(DT_STRSZ, dynstr.0.len()),
+		(DT_SYMENT, SYM_SIZE),
+		(DT_NULL, 0),
+	];
+	let dynamic_len = dynamic.len() * DYN_SIZE;
+
+	let mut shstrtab = StrTab::new();
+	let [hash_name, dynsym_name, dynstr_name, text_name, dynamic_name, shstrtab_name] = [
+		".hash",
+		".dynsym",
+		".dynstr",
+		".text",
+		".dynamic",
+		".shstrtab",
+	]
+	.map(|n| shstrtab.add(n));
+	let shstrtab_off = dynamic_off + dynamic_len;
+	let shoff = align(shstrtab_off + shstrtab.0.len(), 8);
+	let sections = [
+		Section {
+			name: hash_name,
+			kind: SHT_HASH,
+			flags: SHF_ALLOC,
+			addr: hash_off,
+			offset: hash_off,
+			size: hash.len() * 4,
+			link: 2,
+			info: 0,
+			align: 8,
+			entsize: 4,
+		},
+		Section {
+			name: dynsym_name,
+			kind: SHT_DYNSYM,
+			flags: SHF_ALLOC,
+			addr: dynsym_off,
+			offset: dynsym_off,
+			size: nsym * SYM_SIZE,
+			link: 3,
+			info: 1,
+			align: 8,
+			entsize: SYM_SIZE,
+		},
+		Section {
+			name: dynstr_name,
+			kind: SHT_STRTAB,
+			flags: SHF_ALLOC,
+			addr: dynstr_off,
+			offset: dynstr_off,
+			size: dynstr.0.len(),
+			link: 0,
+			info: 0,
+			align: 1,
+			entsize: 0,
+		},
+		Section {
+			name: text_name,
+			kind: SHT_PROGBITS,
+			flags: SHF_ALLOC | SHF_EXECINSTR,
+			addr: text_off,
+			offset: text_off,
+			size: code.len(),
+			link: 0,
+			info: 0,
+			align: 16,
+			entsize: 0,
+		},
+		Section {
+			name: dynamic_name,
+			kind: SHT_DYNAMIC,
+			flags: SHF_ALLOC | SHF_WRITE,
+			addr: dynamic_off,
+			offset: dynamic_off,
+			size: dynamic_len,
+			link: 3,
+			info: 0,
+			align: 8,
+			entsize: DYN_SIZE,
+		},
+		Section {
+			name: shstrtab_name,
+			kind: SHT_STRTAB,
+			flags: 0,
+			addr: 0,
+			offset: shstrtab_off,
+			size: shstrtab.0.len(),
+			link: 0,
+			info: 0,
+			align: 1,
+			entsize: 0,
+		},
+	];
+
+	let mut out = Vec::new();
+	header(
+		&mut out,
+		ET_DYN,
+		PHNUM,
+		shoff,
+		sections.len() + 1,
+		sections.len(),
+	);
+	program_header(&mut out, PT_LOAD, PF_R | PF_X, 0, text_end, PAGE_SIZE);
+	program_header(
+		&mut out,
+		PT_LOAD,
+		PF_R | PF_W,
+		dynamic_off,
+		dynamic_len,
+		PAGE_SIZE,
+	);
+	program_header(
+		&mut out,
+		PT_DYNAMIC,
+		PF_R | PF_W,
+		dynamic_off,
+		dynamic_len,
+		8,
+	);
+	program_header(&mut out, PT_GNU_STACK, PF_R | PF_W, 0, 0, 16);
+
+	pad(&mut out, hash_off);
+	hash.iter().for_each(|&h| u32(&mut out, h));
+
+	pad(&mut out, dynsym_off);
+	symbol(&mut out, 0, 0, 0, 0);
+	for (&name, &(_, offset)) in names.iter().zip(symbols) {
+		symbol(
+			&mut out,
+			name,
+			STB_GLOBAL << 4 | STT_FUNC,
+			TEXT_INDEX,
+			text_off + offset,
+		);
+	}
+	out.extend_from_slice(&dynstr.0);
+
+	pad(&mut out, text_off);
+	out.extend_from_slice(code);
+
+	pad(&mut out, dynamic_off);
+	for (tag, val) in dynamic {
+		u64(&mut out, tag);
+		u64(&mut out, val as u64);
+	}
+
+	out.extend_from_slice(&shstrtab.0);
+
+	pad(&mut out, shoff);
+	out.resize(out.len() + SHDR_SIZE, 0);
+	sections.iter().for_each(|s| s.write(&mut out));
+	out
+}
+
+/// Create a relocatable object file containing `code` and defining `symbols` as functions.
+///
+/// Each symbol is a name with an offset into `code`. `externals` are functions the code calls
+/// which are defined elsewhere, each with the offset of the 32-bit displacement of a
+/// `call` or `jmp` to it.
+pub fn object(code: &[u8], symbols: &[(&str, usize)], externals: &[(&str, usize)]) -> Vec<u8> {
+	const TEXT_INDEX: u16 = 1;
+	const SYMTAB_INDEX: u32 = 3;
+	const STRTAB_INDEX: u32 = 4;
+
+	let mut strtab = StrTab::new();
+	let defined = symbols.iter().map(|&(s, o)| (strtab.add(s), TEXT_INDEX, o));
+	let defined = defined.collect::<Vec<_>>();
+	let undefined = externals.iter().map(|&(s, _)| (strtab.add(s), 0, 0));
+	let undefined = undefined.collect::<Vec<_>>();
+	let nsym = 1 + defined.len() + undefined.len();
+
+	let text_off = align(EHDR_SIZE, 16);
+	let symtab_off = align(text_off + code.len(), 8);
+	let strtab_off = symtab_off + nsym * SYM_SIZE;
+	let rela_off = align(strtab_off + strtab.0.len(), 8);
+	let rela_len = externals.len() * RELA_SIZE;
+
+	let mut shstrtab = StrTab::new();
+	let [text_name, rela_name, symtab_name, strtab_name, stack_name, shstrtab_name] = [
+		".text",
+		".rela.text",
+		".symtab",
+		".strtab",
+		".note.GNU-stack",
+		".shstrtab",
+	]
+	.map(|n| shstrtab.add(n));
+	let shstrtab_off = rela_off + rela_len;
+	let shoff = align(shstrtab_off + shstrtab.0.len(), 8);
+	let sections = [
+		Section {
+			name: text_name,
+			kind: SHT_PROGBITS,
+			flags: SHF_ALLOC | SHF_EXECINSTR,
+			addr: 0,
+			offset: text_off,
+			size: code.len(),
+			link: 0,
+			info: 0,
+			align: 16,
+			entsize: 0,
+		},
+		Section {
+			name: rela_name,
+			kind: SHT_RELA,
+			flags: SHF_INFO_LINK,
+			addr: 0,
+			offset: rela_off,
+			size: rela_len,
+			link: SYMTAB_INDEX,
+			info: TEXT_INDEX.into(),
+			align: 8,
+			entsize: RELA_SIZE,
+		},
+		Section {
+			name: symtab_name,
+			kind: SHT_SYMTAB,
+			flags: 0,
+			addr: 0,
+			offset: symtab_off,
+			size: nsym * SYM_SIZE,
+			link: STRTAB_INDEX,
+			// Only the null symbol is local.
+			info: 1,
+			align: 8,
+			entsize: SYM_SIZE,
+		},
+		Section {
+			name: strtab_name,
+			kind: SHT_STRTAB,
+			flags: 0,
+			addr: 0,
+			offset: strtab_off,
+			size: strtab.0.len(),
+			link: 0,
+			info: 0,
+			align: 1,
+			entsize: 0,
+		},
+		Section {
+			name: stack_name,
+			kind: SHT_PROGBITS,
+			flags: 0,
+			addr: 0,
+			offset: shstrtab_off,
+			size: 0,
+			link: 0,
+			info: 0,
+			align: 1,
+			entsize: 0,
+		},
+		Section {
+			name: shstrtab_name,
+			kind: SHT_STRTAB,
+			flags: 0,
+			addr: 0,
+			offset: shstrtab_off,
+			size: shstrtab.0.len(),
+			link: 0,
+			info: 0,
+			align: 1,
+			entsize: 0,
+		},
+	];
+
+	let mut out = Vec::new();
+	header(
+		&mut out,
+		ET_REL,
+		0,
+		shoff,
+		sections.len() + 1,
+		sections.len(),
+	);
+
+	pad(&mut out, text_off);
+	out.extend_from_slice(code);
+
+	pad(&mut out, symtab_off);
+	symbol(&mut out, 0, 0, 0, 0);
+	for &(name, shndx, value) in &defined {
+		symbol(&mut out, name, STB_GLOBAL << 4 | STT_FUNC, shndx, value);
+	}
+	for &(name, shndx, value) in &undefined {
+		symbol(&mut out, name, STB_GLOBAL << 4 | STT_NOTYPE, shndx, value);
+	}
+	out.extend_from_slice(&strtab.0);
+
+	pad(&mut out, rela_off);
+	for (i, &(_, offset)) in externals.iter().enumerate() {
+		let sym = (1 + defined.len() + i) as u64;
+		u64(&mut out, offset as u64);
+		u64(&mut out, sym << 32 | R_X86_64_PLT32);
+		// The displacement is relative to the end of the instruction.
+		u64(&mut out, -4i64 as u64);
+	}
+
+	out.extend_from_slice(&shstrtab.0);
+
+	pad(&mut out, shoff);
+	out.resize(out.len() + SHDR_SIZE, 0);
+	sections.iter().for_each(|s| s.write(&mut out));
+	out
+}
@@ -0,0 +1,160 @@
+//! Generator for random programs, used for benchmarks and testing.
+
+use crate::Op;
+
+/// Amount of cells generated programs access.
+const CELLS: u64 = 64;
+
+/// Small deterministic PRNG (xorshift64*), so generated programs only depend on the seed.
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		// The state may not be zero.
+		Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+	}
+
+	/// A random number in `0..n`.
+	pub fn below(&mut self, n: u64) -> u64 {
+		self.next_u64() % n
+	}
+}
+
+/// Generate a random program of `len` ops.
+///
+/// Jumps only go back to the previous op, so the program may not terminate.
+pub fn program(len: usize, seed: u64) -> Vec<Op> {
+	let mut rng = Rng::new(seed);
+	(0..len)
+		.map(|i| {
+			let a = rng.below(CELLS) as isize;
+			match rng.below(10) {
+				4..=6 => Op::SetA(a, rng.below(CELLS) as isize),
+				7.. if i > 0 => Op::JmpV(a, -1),
+				_ => Op::SetV(a, rng.below(256) as isize - 128),
+			}
+		})
+		.collect()
+}
+
+/// Amount of cells programs generated by [`stress`] need.
+pub const STRESS_TAPE: usize = 1 << 20;
+
+/// Generate a random program of about `len` ops which terminates, for testing the compilers
+/// with large programs.
+///
+/// The program consists of routines which access and swap cells all over the tape and
+/// conditionally jump a few ops forward with `+A`+B` and `+A`B`, but not past their end. The
+/// first ops call each routine once, and routines may call a later routine, so execution
+/// always ends.
+pub fn stress(len: usize, seed: u64) -> Vec<Op> {
+	const ROUTINE_LEN: usize = 256;
+	let mut rng = Rng::new(seed);
+	let count = (len / ROUTINE_LEN).max(1);
+	let cells = STRESS_TAPE as u64 - 9;
+	// Jumps read their distance from one of the last cells, which are set once at the start.
+	let distances = (1..=8).map(|d| Op::SetV(cells as isize + d, d));
+	let mut ops = distances.collect::<Vec<_>>();
+	let mut calls = (0..count).map(|r| (ops.len() + r, r)).collect::<Vec<_>>();
+	ops.extend((0..=count).map(|_| Op::Ret));
+	let mut starts = Vec::with_capacity(count);
+	for r in 0..count {
+		starts.push(ops.len());
+		let mut called = false;
+		while ops.len() < starts[r] + ROUTINE_LEN {
+			match rng.below(32) {
+				0 if r + 1 < count && !called => {
+					let callee = r + 1 + rng.below((count - r - 1) as u64) as usize;
+					calls.push((ops.len(), callee));
+					ops.push(Op::Ret);
+					called = true;
+				}
+				1..=2 => {
+					let d = cells as isize + 1 + rng.below(8) as isize;
+					ops.push(Op::JmpA(rng.below(10) as isize, d));
+				}
+				17..=18 => {
+					let d = 2 + rng.below(7) as usize;
+					let d = d.min(starts[r] + ROUTINE_LEN - ops.len());
+					ops.push(Op::JmpV(rng.below(10) as isize, d as isize));
+				}
+				3 => ops.push(Op::SetV(0, rng.below(128) as isize)),
+				4..=15 => {
+					let (a, b) = (1 + rng.below(cells - 1), rng.below(cells));
+					ops.push(Op::SetA(a as isize, b as isize));
+				}
+				16 => {
+					let (a, b) = (1 + rng.below(cells - 1), 1 + rng.below(cells - 1));
+					ops.push(Op::Swap(a as isize, b as isize));
+				}
+				_ => {
+					let a = 1 + rng.below(cells - 1);
+					ops.push(Op::SetV(a as isize, rng.below(256) as isize - 128));
+				}
+			}
+		}
+		ops.push(Op::Ret);
+	}
+	for (i, r) in calls {
+		ops[i] = Op::Call(starts[r] as isize - i as isize);
+	}
+	ops
+}
+
+/// Generate a random program of `len` ops like [`stress`], but without calls and `+A`B`.
+///
+/// Either makes [`jit2`](crate::jit2) keep no cells in registers across ops, which this
+/// program doesn't prevent. Its ops use a few cells all over the tape, so cells are both
+/// reused from registers and evicted from them, and `+A`+B` only jumps forward. Cells mostly
+/// get small additions, so jumps are taken as well as not.
+pub fn stress_without_calls(len: usize, seed: u64) -> Vec<Op> {
+	let mut rng = Rng::new(seed);
+	let pool = (0..16)
+		.map(|_| 1 + rng.below(STRESS_TAPE as u64 - 1) as isize)
+		.collect::<Vec<_>>();
+	let cell = |rng: &mut Rng| pool[rng.below(pool.len() as u64) as usize];
+	(0..len)
+		.map(|i| match rng.below(16) {
+			0 => Op::SetV(0, rng.below(128) as isize),
+			1..=3 if i + 2 < len => {
+				let d = 2 + rng.below(7).min((len - i - 3) as u64);
+				Op::JmpV(rng.below(5) as isize - 2, d as isize)
+			}
+			4..=5 => Op::Swap(cell(&mut rng), cell(&mut rng)),
+			6..=7 => Op::SetA(cell(&mut rng), cell(&mut rng)),
+			_ => Op::SetV(cell(&mut rng), rng.below(5) as isize - 2),
+		})
+		.collect()
+}
+
+/// Generate a random op with any operands, favouring edge cases.
+pub fn op(rng: &mut Rng) -> Op {
+	let mut n = || match rng.below(4) {
+		0 => [
+			0,
+			1,
+			-1,
+			isize::MIN,
+			isize::MIN + 1,
+			isize::MAX,
+			isize::MAX - 1,
+		][rng.below(7) as usize],
+		1 => rng.next_u64() as isize,
+		_ => rng.below(2000) as isize - 1000,
+	};
+	let (a, b) = (n(), n());
+	match rng.below(8) {
+		4 => Op::Call(b),
+		5 => Op::Ret,
+		6 => Op::ReadInts(a),
+		7 => Op::Swap(a, b),
+		i => [Op::SetV, Op::SetA, Op::JmpV, Op::JmpA][i as usize](a, b),
+	}
+}
@@ -0,0 +1,609 @@
+//! Interpreters, which execute ops directly.
+
+use crate::cell::Cell;
+use crate::trace::Tracer;
+use crate::{
+	flush, perf, print, print_cell, read_line, report_time, set_output, Config, JumpMode, Op,
+};
+use std::fmt;
+use std::hint::unreachable_unchecked;
+use std::time::Instant;
+
+/// Map every address in the ops to a cell in a tape of `size` cells.
+fn window(ops: Vec<Op>, size: usize) -> Vec<Op> {
+	let cell = |a: isize| a.rem_euclid(size as isize);
+	ops.into_iter()
+		.map(|op| match op {
+			Op::SetV(a, b) => Op::SetV(cell(a), b),
+			Op::SetA(a, b) => Op::SetA(cell(a), cell(b)),
+			Op::JmpA(a, b) => Op::JmpA(a, cell(b)),
+			Op::ReadInts(a) => Op::ReadInts(cell(a)),
+			Op::Swap(a, b) => Op::Swap(cell(a), cell(b)),
+			op => op,
+		})
+		.collect()
+}
+
+/// The length of the run of ops that set a cell starting at each op, which don't change
+/// where execution continues.
+fn straight_runs(ops: &[Op]) -> Vec<usize> {
+	let mut runs = vec![0; ops.len() + 1];
+	for i in (0..ops.len()).rev() {
+		if matches!(ops[i], Op::SetV(..) | Op::SetA(..)) {
+			runs[i] = runs[i + 1] + 1;
+		}
+	}
+	runs
+}
+
+/// Add to a cell and print it if it is cell 0 and `prints` is set. Returns the new value,
+/// which is V.
+///
+/// # Safety
+///
+/// `a` must be an index of `tape`.
+#[inline(always)]
+unsafe fn add<C: Cell>(tape: &mut [C], a: isize, b: C, prints: bool) -> C {
+	let c = tape.get_unchecked_mut(a as usize);
+	*c = c.add(b);
+	(a == 0 && prints).then(|| print_cell(*c));
+	*c
+}
+
+pub fn run(ops: Vec<Op>, cfg: &Config) {
+	run_with::<isize>(ops, cfg)
+}
+
+/// Like [`run`], but with cells of type `C`. Constants in ops are converted to `C`.
+///
+/// Unsigned cells wrap around like the signed cells of the JITs, but print as unsigned in
+/// decimal:
+///
+/// ```
+/// use dynasm_experiments::{capture, parse, run, run_with, Config, Output};
+///
+/// // Cell 1 overflows to the minimum, which is 2^63 unsigned, so the jump falls through and
+/// // cell 0 wraps back from -1, or u64::MAX unsigned, to 1.
+/// let code = "0`+-1 1`+9223372036854775807 1`+1 +-9223372036854775808`+2 0`+2";
+/// let (ops, _) = parse(code.as_bytes().to_vec());
+/// let cfg = Config { tape_len: 2, output: Output::Decimal, ..Config::default() };
+/// let signed = &b"-1\n1\n"[..];
+/// assert_eq!(capture(|| run(ops.clone(), &cfg)).1, signed);
+/// assert_eq!(capture(|| run_with::<isize>(ops.clone(), &cfg)).1, signed);
+/// let unsigned = &b"18446744073709551615\n1\n"[..];
+/// assert_eq!(capture(|| run_with::<u64>(ops.clone(), &cfg)).1, unsigned);
+///
+/// #[cfg(feature = "jit")]
+/// for compile in [dynasm_experiments::jit, dynasm_experiments::jit2] {
+///     let program = compile(ops.clone(), &cfg, dynasm_experiments::PrintCall::Host).unwrap();
+///     assert_eq!(program.execute_captured(&cfg).0, signed);
+/// }
+/// ```
+pub fn run_with<C: Cell>(ops: Vec<Op>, cfg: &Config) {
+	let (ops, tape_len) = match cfg.window {
+		Some(w) => {
+			eprintln!("window: {} cells ({} bytes)", w, w * 8);
+			(window(ops, w), w)
+		}
+		None => (ops, cfg.tape_len),
+	};
+	set_output(cfg.output);
+	let ops = &ops[..]; // This is faster. Don't ask me why.
+	let mut i = cfg.entry;
+	let mut tape = vec![C::default(); tape_len];
+	for (c, &d) in tape.iter_mut().zip(&cfg.tape_data) {
+		*c = C::from_isize(d);
+	}
+	let mut v = C::from_isize(cfg.init_v);
+	let mut calls = Vec::new();
+	let runs = cfg.batch.then(|| straight_runs(ops));
+	let counters = perf::Run::start(cfg);
+	let t = Instant::now();
+	unsafe {
+		while let Some(op) = ops.get(i) {
+			if let Some(runs) = &runs {
+				let n = *runs.get_unchecked(i);
+				if n > 0 {
+					for op in ops.get_unchecked(i..i + n) {
+						let (a, b) = match *op {
+							Op::SetV(a, b) => (a, C::from_isize(b)),
+							Op::SetA(a, b) => (a, *tape.get_unchecked(b as usize)),
+							_ => unreachable_unchecked(),
+						};
+						v = add(&mut tape, a, b, cfg.print);
+					}
+					i += n;
+					continue;
+				}
+			}
+			i += 1;
+			let (a, b) = match *op {
+				Op::SetV(a, b) => (a, C::from_isize(b)),
+				Op::SetA(a, b) => (a, *tape.get_unchecked(b as usize)),
+				Op::JmpV(a, b) => {
+					if C::from_isize(a) != v {
+						i = i.wrapping_add(b as usize).wrapping_sub(1)
+					}
+					continue;
+				}
+				Op::JmpA(a, b) => {
+					if C::from_isize(a) != v {
+						let b = tape.get_unchecked(b as usize).to_isize() as usize;
+						i = match cfg.jump_mode {
+							JumpMode::Relative => i.wrapping_add(b).wrapping_sub(1),
+							JumpMode::Absolute => b,
+						};
+					}
+					continue;
+				}
+				Op::Call(b) => {
+					if calls.len() == cfg.call_depth {
+						break;
+					}
+					calls.push(i);
+					i = i.wrapping_add(b as usize).wrapping_sub(1);
+					continue;
+				}
+				Op::Ret => {
+					match calls.pop() {
+						Some(r) => i = r,
+						None => break,
+					}
+					continue;
+				}
+				Op::ReadInts(a) => {
+					let ints = read_line();
+					for (k, &n) in ints.iter().flatten().enumerate() {
+						tape[(a as usize).wrapping_add(k)] = C::from_isize(n);
+					}
+					v = C::from_isize(ints.map_or(-1, |ints| ints.len() as isize));
+					continue;
+				}
+				Op::Swap(a, b) => {
+					let (a, b) = (a as usize, b as usize);
+					let (x, y) = (*tape.get_unchecked(a), *tape.get_unchecked(b));
+					(*tape.get_unchecked_mut(a), *tape.get_unchecked_mut(b)) = (y, x);
+					continue;
+				}
+			};
+			v = add(&mut tape, a, b, cfg.print);
+		}
+	}
+	flush();
+	report_time(cfg, Instant::now() - t);
+	counters.report();
+}
+
+/// Where compiled code continues after [`fallback`].
+#[cfg(feature = "jit")]
+#[repr(C)]
+pub(crate) struct Resume {
+	/// Index of the next op to execute.
+	ip: usize,
+	/// The new value of V.
+	v: isize,
+}
+
+/// Execute a single op on behalf of compiled code that can't execute it by itself.
+///
+/// `tape` is the tape of the compiled code, `v` the current value of V and `ip` the index of
+/// `op`. `absolute` is whether the cell of `+A`B` holds the index of the next op instead of
+/// its distance and `prints` whether writing cell 0 prints.
+#[cfg(feature = "jit")]
+pub(crate) extern "C" fn fallback(
+	tape: *mut isize,
+	v: isize,
+	ip: usize,
+	op: &Op,
+	absolute: bool,
+	prints: bool,
+) -> Resume {
+	// SAFETY: the compiled code doesn't check tape accesses either.
+	let cell = |i: isize| unsafe { &mut *tape.offset(i) };
+	let (a, b) = match *op {
+		Op::SetV(a, b) => (a, b),
+		Op::SetA(a, b) => (a, *cell(b)),
+		Op::JmpV(a, b) => {
+			let d = if a != v { b as usize } else { 1 };
+			return Resume {
+				ip: ip.wrapping_add(d),
+				v,
+			};
+		}
+		Op::JmpA(a, b) => {
+			let ip = match (a != v, absolute) {
+				(false, _) => ip + 1,
+				(true, false) => ip.wrapping_add(*cell(b) as usize),
+				(true, true) => *cell(b) as usize,
+			};
+			return Resume { ip, v };
+		}
+		Op::Call(_) | Op::Ret | Op::ReadInts(_) | Op::Swap(..) => {
+			unreachable!("{} is always compiled", op)
+		}
+	};
+	let c = cell(a);
+	*c = c.wrapping_add(b);
+	(a == 0 && prints).then(|| print(*c));
+	Resume { ip: ip + 1, v: *c }
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+	/// An op accessed a cell outside the tape.
+	OutOfBounds {
+		op_index: usize,
+		address: isize,
+		/// The byte offset of the op in the source, if known.
+		offset: Option<usize>,
+	},
+	/// A call was nested deeper than the call depth limit.
+	CallDepthExceeded {
+		op_index: usize,
+		offset: Option<usize>,
+	},
+	/// Compiled code was given a tape with fewer cells than it accesses, or none at all.
+	TapeTooSmall { len: usize, required: usize },
+}
+
+impl fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (op_index, offset) = match self {
+			Self::OutOfBounds {
+				op_index, offset, ..
+			}
+			| Self::CallDepthExceeded { op_index, offset } => (op_index, offset),
+			Self::TapeTooSmall { len, required } => {
+				return write!(
+					f,
+					"the program needs a tape of {} cells but it has {}",
+					required, len
+				);
+			}
+		};
+		write!(f, "op {}", op_index)?;
+		if let Some(o) = offset {
+			write!(f, " at byte {}", o)?;
+		}
+		match self {
+			Self::OutOfBounds { address, .. } => {
+				write!(f, " accesses cell {} outside the tape", address)
+			}
+			_ => write!(f, " exceeds the call depth limit"),
+		}
+	}
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Interpreter that checks every tape access.
+///
+/// It is much slower than [`run`], but accessing a cell outside the tape is an error instead
+/// of undefined behaviour. It can also report questionable behaviour of programs.
+///
+/// Its state can be saved with [`snapshot`](Self::snapshot) and rolled back with
+/// [`restore`](Self::restore):
+///
+/// ```
+/// use dynasm_experiments::{parse, Checked, Config};
+///
+/// let (ops, _) = parse(b"1`+1 1`+1 1`+1".to_vec());
+/// let mut vm = Checked::new(&ops, &Config::default());
+/// vm.step().unwrap();
+/// let snapshot = vm.snapshot();
+/// while vm.step().unwrap() {}
+/// assert_eq!(vm.tape()[1], 3);
+///
+/// vm.restore(&snapshot);
+/// assert_eq!(vm.tape()[1], 1);
+/// vm.tape_mut()[1] = 10;
+/// while vm.step().unwrap() {}
+/// assert_eq!(vm.tape()[1], 12);
+/// ```
+///
+/// Calls nested deeper than [`Config::call_depth`] are an error:
+///
+/// ```
+/// use dynasm_experiments::{parse, Checked, Config, RuntimeError};
+///
+/// // Op 2 calls itself until the limit is reached.
+/// let (ops, _) = parse(b">2 < 1`+1 >-1 <".to_vec());
+/// let cfg = Config { call_depth: 3, ..Config::default() };
+/// let mut vm = Checked::new(&ops, &cfg);
+/// let e = loop {
+///     match vm.step() {
+///         Ok(true) => (),
+///         Ok(false) => unreachable!(),
+///         Err(e) => break e,
+///     }
+/// };
+/// assert!(matches!(e, RuntimeError::CallDepthExceeded { op_index: 3, offset: None }));
+/// assert_eq!(vm.tape()[1], 3);
+/// ```
+///
+/// With [`Config::spans`], errors also tell where the op is in the source:
+///
+/// ```
+/// use dynasm_experiments::{parse_with_spans, run_checked, Config, RuntimeError};
+///
+/// let (ops, spans, _) = parse_with_spans(b"1`+1\n  3`+1 # oops\n".to_vec(), false).unwrap();
+/// let cfg = Config { tape_len: 2, spans: Some(spans), ..Config::default() };
+/// let e = run_checked(ops, &cfg).unwrap_err();
+/// assert!(matches!(e, RuntimeError::OutOfBounds { op_index: 1, address: 3, offset: Some(7) }));
+/// assert_eq!(e.to_string(), "op 1 at byte 7 accesses cell 3 outside the tape");
+/// ```
+pub struct Checked<'a> {
+	ops: &'a [Op],
+	tape: Vec<isize>,
+	v: isize,
+	ip: usize,
+	/// The indices of the ops to return to.
+	calls: Vec<usize>,
+	call_depth: usize,
+	/// Which cells have been written to, if reads of unwritten cells should be reported.
+	written: Option<Vec<bool>>,
+	/// How often each op has been executed, if coverage should be reported.
+	executed: Option<Vec<u64>>,
+	/// Halt once this cell is written with this value.
+	until: Option<(isize, isize)>,
+	/// Whether the program was halted because of `until`.
+	reached: bool,
+	/// Amount of ops executed.
+	steps: u64,
+	tracer: Option<Tracer>,
+	/// Whether writes to cell 0 print.
+	prints: bool,
+	jump_mode: JumpMode,
+	/// The byte offset in the source of each op, for errors.
+	spans: Option<Vec<usize>>,
+}
+
+/// The state of a program saved by [`Checked::snapshot`].
+#[derive(Clone)]
+pub struct Snapshot {
+	tape: Vec<isize>,
+	v: isize,
+	ip: usize,
+	calls: Vec<usize>,
+}
+
+impl Snapshot {
+	pub fn tape(&self) -> &[isize] {
+		&self.tape
+	}
+
+	pub fn v(&self) -> isize {
+		self.v
+	}
+
+	/// The index of the next op.
+	pub fn ip(&self) -> usize {
+		self.ip
+	}
+
+	/// The indices of the ops that ongoing calls return to.
+	pub fn calls(&self) -> &[usize] {
+		&self.calls
+	}
+}
+
+impl<'a> Checked<'a> {
+	pub fn new(ops: &'a [Op], cfg: &Config) -> Self {
+		set_output(cfg.output);
+		let mut tape = vec![0; cfg.tape_len];
+		let data = cfg.tape_data.len().min(tape.len());
+		tape[..data].copy_from_slice(&cfg.tape_data[..data]);
+		let mut written = cfg.warn_uninit.then(|| vec![false; cfg.tape_len]);
+		if let Some(w) = &mut written {
+			w[..data].fill(true);
+		}
+		Self {
+			ops,
+			tape,
+			v: cfg.init_v,
+			ip: cfg.entry,
+			calls: Vec::new(),
+			call_depth: cfg.call_depth,
+			written,
+			executed: cfg.coverage.then(|| vec![0; ops.len()]),
+			until: cfg.run_until,
+			reached: false,
+			steps: 0,
+			tracer: cfg.trace.as_ref().and_then(|path| {
+				Tracer::new(path, cfg.trace_format)
+					.map_err(|e| eprintln!("warning: not tracing to {}: {}", path.display(), e))
+					.ok()
+			}),
+			prints: cfg.print,
+			jump_mode: cfg.jump_mode,
+			spans: cfg.spans.clone(),
+		}
+	}
+
+	fn index(&self, address: isize) -> Result<usize, RuntimeError> {
+		usize::try_from(address)
+			.ok()
+			.filter(|&i| i < self.tape.len())
+			.ok_or(RuntimeError::OutOfBounds {
+				op_index: self.ip,
+				address,
+				offset: self.offset(),
+			})
+	}
+
+	/// The byte offset in the source of the current op, if known.
+	fn offset(&self) -> Option<usize> {
+		self.spans.as_ref()?.get(self.ip).copied()
+	}
+
+	/// Read a cell used as an operand.
+	fn read(&mut self, address: isize) -> Result<isize, RuntimeError> {
+		let i = self.index(address)?;
+		if let Some(w) = self.written.as_mut().filter(|w| !w[i]) {
+			eprintln!(
+				"warning: op {} ({}) reads cell {} before it is written",
+				self.ip, self.ops[self.ip], address
+			);
+			// Only warn once per cell.
+			w[i] = true;
+		}
+		Ok(self.tape[i])
+	}
+
+	/// Add to a cell, which also counts as initializing it.
+	fn add(&mut self, address: isize, value: isize) -> Result<isize, RuntimeError> {
+		let i = self.index(address)?;
+		self.write(address, self.tape[i].wrapping_add(value))
+	}
+
+	/// Set a cell, which also counts as initializing it.
+	fn write(&mut self, address: isize, value: isize) -> Result<isize, RuntimeError> {
+		let i = self.index(address)?;
+		if let Some(w) = self.written.as_mut() {
+			w[i] = true;
+		}
+		self.tape[i] = value;
+		self.reached |= self.until == Some((address, value));
+		Ok(value)
+	}
+
+	/// Save the tape, V, the index of the next op and the ongoing calls.
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot {
+			tape: self.tape.clone(),
+			v: self.v,
+			ip: self.ip,
+			calls: self.calls.clone(),
+		}
+	}
+
+	/// Continue from a saved state.
+	///
+	/// Output that was already printed and the state used for reports, such as which ops were
+	/// executed, are not rolled back.
+	pub fn restore(&mut self, snapshot: &Snapshot) {
+		self.tape.clone_from(&snapshot.tape);
+		self.v = snapshot.v;
+		self.ip = snapshot.ip;
+		self.calls.clone_from(&snapshot.calls);
+		self.reached = false;
+	}
+
+	pub fn tape(&self) -> &[isize] {
+		&self.tape
+	}
+
+	pub fn tape_mut(&mut self) -> &mut [isize] {
+		&mut self.tape
+	}
+
+	/// The indices of the ops that ongoing calls return to.
+	pub fn calls(&self) -> &[usize] {
+		&self.calls
+	}
+
+	/// How often each op has been executed, if [`Config::coverage`] is set.
+	pub fn executions(&self) -> Option<&[u64]> {
+		self.executed.as_deref()
+	}
+
+	/// Execute a single op. Returns `false` if the program has finished.
+	pub fn step(&mut self) -> Result<bool, RuntimeError> {
+		let Some(&op) = self.ops.get(self.ip) else {
+			return Ok(false);
+		};
+		if let Some(e) = self.executed.as_mut() {
+			e[self.ip] += 1;
+		}
+		if let Some(t) = self.tracer.as_mut() {
+			t.event(self.ip, op, self.v);
+		}
+		self.steps += 1;
+		self.reached = false;
+		let (a, b) = match op {
+			Op::SetV(a, b) => (a, b),
+			Op::SetA(a, b) => (a, self.read(b)?),
+			Op::JmpV(a, b) => {
+				self.ip = self
+					.ip
+					.wrapping_add(if a != self.v { b as usize } else { 1 });
+				return Ok(true);
+			}
+			Op::JmpA(a, b) => {
+				self.ip = match (a != self.v, self.jump_mode) {
+					(false, _) => self.ip + 1,
+					(true, JumpMode::Relative) => self.ip.wrapping_add(self.read(b)? as usize),
+					(true, JumpMode::Absolute) => self.read(b)? as usize,
+				};
+				return Ok(true);
+			}
+			Op::Call(b) => {
+				if self.calls.len() == self.call_depth {
+					return Err(RuntimeError::CallDepthExceeded {
+						op_index: self.ip,
+						offset: self.offset(),
+					});
+				}
+				self.calls.push(self.ip + 1);
+				self.ip = self.ip.wrapping_add(b as usize);
+				return Ok(true);
+			}
+			Op::Ret => {
+				let Some(r) = self.calls.pop() else {
+					return Ok(false);
+				};
+				self.ip = r;
+				return Ok(true);
+			}
+			Op::ReadInts(a) => {
+				let ints = read_line();
+				for (k, &n) in ints.iter().flatten().enumerate() {
+					self.write(a.wrapping_add(k as isize), n)?;
+				}
+				self.v = ints.map_or(-1, |ints| ints.len() as isize);
+				self.ip += 1;
+				return Ok(!self.reached);
+			}
+			Op::Swap(a, b) => {
+				let (x, y) = (self.read(a)?, self.read(b)?);
+				self.write(a, y)?;
+				self.write(b, x)?;
+				self.ip += 1;
+				return Ok(!self.reached);
+			}
+		};
+		self.v = self.add(a, b)?;
+		(a == 0 && self.prints).then(|| print(self.v));
+		self.ip += 1;
+		Ok(!self.reached)
+	}
+}
+
+/// Run a program with the checked interpreter and return the final tape.
+pub fn run_checked(ops: Vec<Op>, cfg: &Config) -> Result<Vec<isize>, RuntimeError> {
+	let mut vm = Checked::new(&ops, cfg);
+	let counters = perf::Run::start(cfg);
+	let t = Instant::now();
+	let res = (|| {
+		while vm.step()? {}
+		Ok(())
+	})();
+	flush();
+	report_time(cfg, Instant::now() - t);
+	counters.report();
+	if let Some((cell, value)) = vm.until.filter(|_| vm.reached) {
+		eprintln!("cell {} reached {} after {} steps", cell, value, vm.steps);
+		eprintln!("next op: {}  V: {}", vm.ip, vm.v);
+		for (i, c) in vm.tape.iter().enumerate().filter(|(_, c)| **c != 0) {
+			eprintln!("{:>6}  {}", i, c);
+		}
+	}
+	if let Some(e) = vm.executed {
+		let uncovered = e.iter().filter(|e| **e == 0).count();
+		eprintln!("{} of {} ops not executed", uncovered, ops.len());
+		for (i, _) in e.iter().enumerate().filter(|(_, e)| **e == 0) {
+			eprintln!("{:>6}  {}", i, ops[i]);
+		}
+	}
+	res.map(|()| vm.tape)
+}
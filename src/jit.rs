@@ -0,0 +1,1396 @@
+//! JIT compilers, which translate ops to x86-64 machine code.
+
+use crate::interpreter::fallback;
+use crate::regalloc::{self, Allocator};
+use crate::tape::Tape;
+use crate::{
+	capture, flush, perf, print, read_ints, report_time, set_output, Config, JumpMode, Op,
+	RuntimeError,
+};
+use dynasmrt::x64::{Rq, X64Relocation};
+use dynasmrt::{
+	dynasm, Assembler, DynamicLabel, DynasmApi, DynasmLabelApi, ExecutableBuffer, Register,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How compiled code calls [`print`].
+#[derive(Clone, Copy)]
+pub enum PrintCall {
+	/// Call [`print`] in this process by its absolute address.
+	Host,
+	/// Call a minimal implementation appended to the code, which makes the code position
+	/// independent.
+	Bundled,
+	/// Call a function named `print` outside the code, which is left for a linker to resolve.
+	///
+	/// The code is position independent, but can't be executed as is.
+	External,
+}
+
+impl PrintCall {
+	fn emit(self, jit: &mut Assembler<X64Relocation>) {
+		match self {
+			Self::Host => dynasm!(jit ; mov rax, QWORD print as *const () as _ ; call rax),
+			Self::Bundled | Self::External => dynasm!(jit ; call ->print),
+		}
+	}
+
+	/// Emit the implementation of `print` if it is bundled with the code, or a jump to the
+	/// external function.
+	///
+	/// The bundled implementation writes the UTF-8 encoding of the character directly to
+	/// stdout.
+	///
+	/// For [`External`](Self::External) the offset of the jump's 32-bit displacement is
+	/// returned.
+	fn emit_runtime(self, jit: &mut Assembler<X64Relocation>) -> Option<usize> {
+		match self {
+			Self::Host => return None,
+			Self::Bundled => (),
+			Self::External => {
+				dynasm!(jit ; ->print: ; .bytes [0xe9, 0, 0, 0, 0]);
+				return Some(jit.offset().0 - 4);
+			}
+		}
+		dynasm!(jit
+			; ->print:
+			; sub rsp, 8
+			; mov eax, edi
+			; cmp eax, 0x80
+			; jb >one
+			; cmp eax, 0x800
+			; jb >two
+			; cmp eax, 0xd800
+			; jb >three
+			; cmp eax, 0xe000
+			; jb >bad
+			; cmp eax, 0x10000
+			; jb >three
+			; cmp eax, 0x110000
+			; jb >four
+			; bad:
+			; mov eax, 0xfffd
+			; jmp >three
+			; one:
+			; mov [rsp], al
+			; mov edx, 1
+			; jmp >write
+			; two:
+			; mov ecx, eax
+			; shr ecx, 6
+			; or ecx, 0xc0
+			; mov [rsp], cl
+			; and eax, 0x3f
+			; or eax, 0x80
+			; mov [rsp + 1], al
+			; mov edx, 2
+			; jmp >write
+			; three:
+			; mov ecx, eax
+			; shr ecx, 12
+			; or ecx, 0xe0
+			; mov [rsp], cl
+			; mov ecx, eax
+			; shr ecx, 6
+			; and ecx, 0x3f
+			; or ecx, 0x80
+			; mov [rsp + 1], cl
+			; and eax, 0x3f
+			; or eax, 0x80
+			; mov [rsp + 2], al
+			; mov edx, 3
+			; jmp >write
+			; four:
+			; mov ecx, eax
+			; shr ecx, 18
+			; or ecx, 0xf0
+			; mov [rsp], cl
+			; mov ecx, eax
+			; shr ecx, 12
+			; and ecx, 0x3f
+			; or ecx, 0x80
+			; mov [rsp + 1], cl
+			; mov ecx, eax
+			; shr ecx, 6
+			; and ecx, 0x3f
+			; or ecx, 0x80
+			; mov [rsp + 2], cl
+			; and eax, 0x3f
+			; or eax, 0x80
+			; mov [rsp + 3], al
+			; mov edx, 4
+			; write:
+			; mov eax, 1 // write
+			; mov edi, 1 // stdout
+			; mov rsi, rsp
+			; syscall
+			; add rsp, 8
+			; ret
+		);
+		None
+	}
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+	/// A jump would continue at an op that does not exist.
+	JumpOutOfRange {
+		op_index: usize,
+		computed_target: isize,
+	},
+	/// An op needs functions of the host process, but the code is compiled to run outside of
+	/// it.
+	///
+	/// ```
+	/// use dynasm_experiments::{jit, jit2, parse, CompileError, Config, PrintCall};
+	///
+	/// // `+A`B` runs in the interpreter and `,A` reads input in Rust.
+	/// for src in ["1`+1 +0`2", "1`+1 ,1"] {
+	///     let (ops, _) = parse(src.as_bytes().to_vec());
+	///     for compile in [jit, jit2] {
+	///         for print in [PrintCall::Bundled, PrintCall::External] {
+	///             let e = compile(ops.clone(), &Config::default(), print).err().unwrap();
+	///             assert!(matches!(e, CompileError::Unsupported { op_index: 1, .. }));
+	///         }
+	///     }
+	/// }
+	/// ```
+	Unsupported {
+		op_index: usize,
+		op: Op,
+		/// Why it can't be compiled.
+		reason: &'static str,
+	},
+}
+
+impl fmt::Display for CompileError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::JumpOutOfRange {
+				op_index,
+				computed_target,
+			} => {
+				write!(
+					f,
+					"op {} jumps to op {}, which does not exist",
+					op_index, computed_target
+				)
+			}
+			Self::Unsupported {
+				op_index,
+				op,
+				reason,
+			} => write!(f, "op {} ({}) {}", op_index, op, reason),
+		}
+	}
+}
+
+impl std::error::Error for CompileError {}
+
+/// Get the displacement of cell `a` from the tape in rbx.
+///
+/// If the displacement doesn't fit in 32 bits, rax is set to the address of the cell with
+/// `lea` instead and `None` is returned.
+fn cell(jit: &mut Assembler<X64Relocation>, a: isize) -> Option<i32> {
+	let d = a.checked_mul(8).and_then(|d| i32::try_from(d).ok());
+	if d.is_none() {
+		dynasm!(jit
+			; mov rax, QWORD a as _
+			; lea rax, [rbx + rax * 8]
+		);
+	}
+	d
+}
+
+/// Get the label of the op that the `+A`+B` or `>B` op at index `i` with distance `b`
+/// continues at, which is the op `b` ops further like in the interpreters. The index just
+/// past the last op ends the program at `end`.
+fn jump_target(
+	labels: &[DynamicLabel],
+	end: DynamicLabel,
+	i: usize,
+	b: isize,
+) -> Result<DynamicLabel, CompileError> {
+	match i.checked_add_signed(b) {
+		Some(t) if t < labels.len() => Ok(labels[t]),
+		Some(t) if t == labels.len() => Ok(end),
+		_ => Err(CompileError::JumpOutOfRange {
+			op_index: i,
+			computed_target: (i as isize).wrapping_add(b),
+		}),
+	}
+}
+
+/// Emit a comparison of V, which is in register `v` or otherwise in the slot `frame` points
+/// to, with the constant `a` of `+A`+B`.
+fn emit_cmp_v(jit: &mut Assembler<X64Relocation>, v: Option<Rq>, frame: Rq, a: isize) {
+	match (v, i32::try_from(a)) {
+		(Some(r), Ok(a)) => dynasm!(jit ; cmp Rq(r.code()), DWORD a),
+		(None, Ok(a)) => dynasm!(jit ; cmp QWORD [Rq(frame.code())], DWORD a),
+		(Some(r), Err(_)) => dynasm!(jit ; mov rax, QWORD a as _ ; cmp Rq(r.code()), rax),
+		(None, Err(_)) => dynasm!(jit ; mov rax, QWORD a as _ ; cmp [Rq(frame.code())], rax),
+	}
+}
+
+/// Look up where the targets of branches recorded while emitting code ended up.
+fn resolve_branches(
+	jit: &Assembler<X64Relocation>,
+	branches: &[(usize, DynamicLabel)],
+) -> Box<[(usize, usize)]> {
+	let labels = jit.labels();
+	let target = |l| labels.resolve_dynamic(l).unwrap().0;
+	branches.iter().map(|&(at, l)| (at, target(l))).collect()
+}
+
+/// Set to make compiled code with a time limit end before its next op.
+static STOP: AtomicBool = AtomicBool::new(false);
+/// The amount of ops compiled code with a time limit started.
+static STEPS: AtomicU64 = AtomicU64::new(0);
+
+/// Emit code that counts an op and ends the program if [`STOP`] is set.
+fn emit_step(jit: &mut Assembler<X64Relocation>, end: DynamicLabel) {
+	dynasm!(jit
+		; mov rax, QWORD STEPS.as_ptr() as _
+		; add QWORD [rax], 1
+		; mov rax, QWORD STOP.as_ptr() as _
+		; cmp BYTE [rax], 0
+		; jne =>end
+	);
+}
+
+/// Emit code that reads the time stamp counter into rax once all earlier instructions
+/// finished and before any later one starts. rcx and rdx are clobbered.
+fn emit_rdtscp(jit: &mut Assembler<X64Relocation>) {
+	dynasm!(jit
+		; rdtscp
+		; lfence
+		; shl rdx, 32
+		; or rax, rdx
+	);
+}
+
+/// With a time limit, emit the label jumps to the op go to along with [`emit_step`], and
+/// return a new label for the op itself to define.
+fn bounded_label(
+	jit: &mut Assembler<X64Relocation>,
+	cfg: &Config,
+	lbl: DynamicLabel,
+	end: DynamicLabel,
+) -> DynamicLabel {
+	if cfg.timeout.is_none() {
+		return lbl;
+	}
+	dynasm!(jit ; =>lbl);
+	emit_step(jit, end);
+	jit.new_dynamic_label()
+}
+
+/// Machine code produced by [`jit`] or [`jit2`].
+pub struct JitProgram {
+	code: ExecutableBuffer,
+	/// The compiled ops, which the code refers to when it needs to call [`fallback`].
+	_ops: Box<[Op]>,
+	print_relocation: Option<usize>,
+	spills: usize,
+	op_offsets: Box<[usize]>,
+	branches: Box<[(usize, usize)]>,
+	required_tape_len: Option<usize>,
+	cycles: bool,
+}
+
+impl JitProgram {
+	/// The generated machine code.
+	pub fn code(&self) -> &[u8] {
+		&self.code
+	}
+
+	/// The offset of the 32-bit PC-relative displacement to `print` in the code, if it was
+	/// compiled with [`PrintCall::External`].
+	pub fn print_relocation(&self) -> Option<usize> {
+		self.print_relocation
+	}
+
+	/// The amount of cells [`jit2`] evicted from registers, which is always 0 for [`jit`].
+	pub fn spills(&self) -> usize {
+		self.spills
+	}
+
+	/// The offset in the code at which each op starts, followed by the offset right after the
+	/// last op.
+	pub fn op_offsets(&self) -> &[usize] {
+		&self.op_offsets
+	}
+
+	/// The jumps and calls to ops in the code, each as the offset right after it and the
+	/// offset it goes to. The displacement encoded in the branch is their difference.
+	pub fn branches(&self) -> &[(usize, usize)] {
+		&self.branches
+	}
+
+	/// The amount of cells a tape needs for every cell the code accesses, or `None` if that
+	/// isn't known because the program accesses cells before cell 0 or reads input, which
+	/// may write any amount of cells.
+	///
+	/// Running the code on a smaller tape fails before any of it runs, unless accesses
+	/// beyond the tape are caught with [`Config::guard_pages`]. Every tape needs at least one
+	/// cell.
+	///
+	/// ```
+	/// use dynasm_experiments::{jit, parse, Config, PrintCall, RuntimeError};
+	///
+	/// let (ops, _) = parse(b"1`+5 3`1".to_vec());
+	/// let cfg = Config::default();
+	/// let program = jit(ops, &cfg, PrintCall::Host).unwrap();
+	/// assert_eq!(program.required_tape_len(), Some(4));
+	///
+	/// let mut tape = [0; 3];
+	/// let e = program.execute_on(&cfg, &mut tape).unwrap_err();
+	/// assert!(matches!(e, RuntimeError::TapeTooSmall { len: 3, required: 4 }));
+	/// assert_eq!(tape, [0; 3]);
+	///
+	/// let mut tape = [0; 4];
+	/// assert_eq!(program.execute_on(&cfg, &mut tape).unwrap(), b"");
+	/// assert_eq!(tape, [0, 5, 0, 5]);
+	///
+	/// let (ops, _) = parse(b"<".to_vec());
+	/// let program = jit(ops, &cfg, PrintCall::Host).unwrap();
+	/// let e = program.execute_on(&cfg, &mut []).unwrap_err();
+	/// assert!(matches!(e, RuntimeError::TapeTooSmall { len: 0, required: 1 }));
+	///
+	/// let (ops, _) = parse(b",1".to_vec());
+	/// let program = jit(ops, &cfg, PrintCall::Host).unwrap();
+	/// assert_eq!(program.required_tape_len(), None);
+	/// ```
+	pub fn required_tape_len(&self) -> Option<usize> {
+		self.required_tape_len
+	}
+
+	/// Check that a tape of `len` cells is large enough for the code.
+	fn check_tape(&self, len: usize) -> Result<(), RuntimeError> {
+		let required = self.required_tape_len.unwrap_or(0).max(1);
+		if len < required {
+			return Err(RuntimeError::TapeTooSmall { len, required });
+		}
+		Ok(())
+	}
+
+	/// The entry point of the compiled code, which takes a pointer to cell 0 of the tape.
+	///
+	/// # Safety
+	///
+	/// The code does not check any tape accesses. Calling the function is undefined
+	/// behaviour unless the tape has more cells than the highest address the program accesses.
+	///
+	/// The function is only valid while `self` is alive. Calling it afterwards is undefined
+	/// behaviour.
+	///
+	/// If the program was compiled with [`PrintCall::Host`], output needs to be written out
+	/// with [`flush`](crate::flush) afterwards.
+	///
+	/// # Example
+	///
+	/// ```
+	/// use dynasm_experiments::{flush, jit2, parse, Config, PrintCall};
+	///
+	/// let (ops, _) = parse(b"1`+4 1`+38".to_vec());
+	/// let program = jit2(ops, &Config::default(), PrintCall::Host).unwrap();
+	/// let mut tape = [0; 2];
+	/// // SAFETY: the program only accesses cells 0 and 1 and program outlives f.
+	/// let f = unsafe { program.as_raw_fn() };
+	/// f(tape.as_mut_ptr());
+	/// flush();
+	/// assert_eq!(tape[1], 42);
+	/// ```
+	pub unsafe fn as_raw_fn(&self) -> extern "C" fn(*mut isize) {
+		core::mem::transmute(self.code.ptr(dynasmrt::AssemblyOffset(0)))
+	}
+
+	/// Like [`as_raw_fn`](Self::as_raw_fn), for code compiled with [`Config::cycles`], whose
+	/// function returns how many cycles of the time stamp counter it ran. `None` for other
+	/// code.
+	///
+	/// # Safety
+	///
+	/// As with [`as_raw_fn`](Self::as_raw_fn).
+	///
+	/// ```
+	/// use dynasm_experiments::{jit, jit2, parse, Config, PrintCall};
+	///
+	/// let (ops, _) = parse(b"1`+4 >3 2`1 < 1`+38 <".to_vec());
+	/// let cfg = Config { cycles: true, ..Config::default() };
+	/// for compile in [jit, jit2] {
+	///     let program = compile(ops.clone(), &cfg, PrintCall::Host).unwrap();
+	///     let mut tape = [0; 3];
+	///     // SAFETY: the program only accesses cells 1 and 2 and program outlives f.
+	///     let f = unsafe { program.as_raw_timed_fn() }.unwrap();
+	///     assert!(f(tape.as_mut_ptr()) > 0);
+	///     assert_eq!(tape, [0, 42, 42]);
+	///
+	///     let program = compile(ops.clone(), &Config::default(), PrintCall::Host).unwrap();
+	///     assert!(unsafe { program.as_raw_timed_fn() }.is_none());
+	/// }
+	/// ```
+	pub unsafe fn as_raw_timed_fn(&self) -> Option<extern "C" fn(*mut isize) -> u64> {
+		self.cycles
+			.then(|| core::mem::transmute(self.code.ptr(dynasmrt::AssemblyOffset(0))))
+	}
+
+	/// Call the compiled code with a fresh tape, which starts with [`Config::tape_data`].
+	/// Returns how long it ran.
+	///
+	/// If the code was compiled with [`Config::timeout`], it ends once it ran that long and
+	/// the amount of ops it executed per second is printed. With [`Config::cycles`], the
+	/// cycles it measured are printed.
+	pub fn execute(&self, cfg: &Config) -> Duration {
+		if !cfg.guard_pages {
+			if let Err(e) = self.check_tape(cfg.tape_len) {
+				panic!("{}", e);
+			}
+		}
+		let mut tape = Tape::new(cfg.tape_len, cfg.guard_pages, &cfg.tape_data);
+		// SAFETY: the tape is as large as configured. Accesses beyond it are only caught with
+		// guard pages.
+		let f = unsafe { self.as_raw_fn() };
+		set_output(cfg.output);
+		let watchdog = cfg.timeout.map(|limit| {
+			STOP.store(false, Ordering::Relaxed);
+			STEPS.store(0, Ordering::Relaxed);
+			let (done, wait) = mpsc::channel::<()>();
+			let h = thread::spawn(move || {
+				if wait.recv_timeout(limit) == Err(RecvTimeoutError::Timeout) {
+					STOP.store(true, Ordering::Relaxed);
+				}
+			});
+			(done, h)
+		});
+		// SAFETY: as with f.
+		let timed = unsafe { self.as_raw_timed_fn() };
+		let counters = perf::Run::start(cfg);
+		let t = Instant::now();
+		let cycles = match timed {
+			Some(f) => Some(f(tape.as_mut_ptr())),
+			None => {
+				f(tape.as_mut_ptr());
+				None
+			}
+		};
+		flush();
+		let t = Instant::now() - t;
+		if let Some((done, h)) = watchdog {
+			drop(done);
+			h.join().unwrap();
+			let steps = STEPS.load(Ordering::Relaxed);
+			let stopped = if STOP.load(Ordering::Relaxed) {
+				", stopped at the time limit"
+			} else {
+				""
+			};
+			let rate = steps as f64 / t.as_secs_f64();
+			eprintln!("{} ops ({:.0} ops per second){}", steps, rate, stopped);
+		}
+		report_time(cfg, t);
+		if let Some(c) = cycles {
+			eprintln!("{} cycles", c);
+		}
+		counters.report();
+		t
+	}
+
+	/// Run the code on a fresh tape like [`JitProgram::execute`], and return what it printed
+	/// and the final tape instead of writing them out.
+	///
+	/// ```
+	/// use dynasm_experiments::{jit, parse, Config, PrintCall};
+	///
+	/// let (ops, _) = parse(b"1`+3 0`+72 0`1".to_vec());
+	/// let cfg = Config { tape_len: 2, ..Config::default() };
+	/// let program = jit(ops, &cfg, PrintCall::Host).unwrap();
+	/// let (out, tape) = program.execute_captured(&cfg);
+	/// assert_eq!(out, b"HK");
+	/// assert_eq!(tape, [75, 3]);
+	/// ```
+	pub fn execute_captured(&self, cfg: &Config) -> (Vec<u8>, Vec<isize>) {
+		if !cfg.guard_pages {
+			if let Err(e) = self.check_tape(cfg.tape_len) {
+				panic!("{}", e);
+			}
+		}
+		let mut tape = Tape::new(cfg.tape_len, cfg.guard_pages, &cfg.tape_data);
+		// SAFETY: as with execute.
+		let f = unsafe { self.as_raw_fn() };
+		set_output(cfg.output);
+		let ((), out) = capture(|| f(tape.as_mut_ptr()));
+		(out, tape.cells(cfg.tape_len).to_vec())
+	}
+
+	/// Run the code on the given tape and return what it printed.
+	///
+	/// With [`Config::entry`] and [`Config::init_v`] this continues a program from a state
+	/// saved by another backend, as long as no calls are ongoing:
+	///
+	/// ```
+	/// use dynasm_experiments::{jit, parse, Checked, Config, PrintCall};
+	///
+	/// let (ops, _) = parse(b"1`+3 0`+72 0`1".to_vec());
+	/// let cfg = Config { tape_len: 2, ..Config::default() };
+	/// let mut vm = Checked::new(&ops, &cfg);
+	/// vm.step().unwrap();
+	/// let state = vm.snapshot();
+	///
+	/// let cfg = Config { entry: state.ip(), init_v: state.v(), ..cfg };
+	/// let program = jit(ops, &cfg, PrintCall::Host).unwrap();
+	/// let mut tape = state.tape().to_vec();
+	/// assert_eq!(program.execute_on(&cfg, &mut tape).unwrap(), b"HK");
+	/// assert_eq!(tape, [75, 3]);
+	/// ```
+	pub fn execute_on(&self, cfg: &Config, tape: &mut [isize]) -> Result<Vec<u8>, RuntimeError> {
+		self.check_tape(tape.len())?;
+		debug_assert!(tape.as_ptr().is_aligned());
+		// SAFETY: the tape is as large as the code accesses, if that is known.
+		let f = unsafe { self.as_raw_fn() };
+		set_output(cfg.output);
+		Ok(capture(|| f(tape.as_mut_ptr())).1)
+	}
+}
+
+/// The amount of cells a tape needs for the cells the ops access, if it is known.
+fn required_tape_len(ops: &[Op]) -> Option<usize> {
+	let mut len = 0;
+	for op in ops {
+		let cells = match *op {
+			Op::SetV(a, _) => [Some(a), None],
+			Op::SetA(a, b) | Op::Swap(a, b) => [Some(a), Some(b)],
+			Op::JmpA(_, b) => [Some(b), None],
+			Op::JmpV(..) | Op::Call(_) | Op::Ret => [None, None],
+			Op::ReadInts(_) => return None,
+		};
+		for c in cells.into_iter().flatten() {
+			len = len.max(usize::try_from(c).ok()?.checked_add(1)?);
+		}
+	}
+	Some(len)
+}
+
+/// Whether an op is part of a call, which uses the native stack.
+fn is_call(op: &Op) -> bool {
+	matches!(op, Op::Call(_) | Op::Ret)
+}
+
+/// Whether an op can't be compiled and needs to be executed by [`fallback`].
+fn needs_fallback(op: &Op) -> bool {
+	matches!(op, Op::JmpA(..))
+}
+
+/// Emit a call to [`read_ints`] for the `,A` op at index `i`, which leaves V in `rax`.
+fn emit_read_ints(
+	jit: &mut Assembler<X64Relocation>,
+	ops: &[Op],
+	i: usize,
+	print: PrintCall,
+) -> Result<(), CompileError> {
+	let Op::ReadInts(a) = ops[i] else {
+		unreachable!()
+	};
+	if !matches!(print, PrintCall::Host) {
+		return Err(CompileError::Unsupported {
+			op_index: i,
+			op: ops[i],
+			reason: "needs the host to read input",
+		});
+	}
+	dynasm!(jit
+		; mov rdi, rbx
+		; mov rsi, QWORD a as _
+		; mov rax, QWORD read_ints as *const () as _
+		; call rax
+	);
+	Ok(())
+}
+
+/// Emit a call to [`fallback`] for the op at index `i`, which leaves the index of the next op in
+/// `rax` and V in `rdx`.
+///
+/// V must be in `rsi`.
+fn emit_fallback(
+	jit: &mut Assembler<X64Relocation>,
+	ops: &[Op],
+	i: usize,
+	print: PrintCall,
+	cfg: &Config,
+) -> Result<(), CompileError> {
+	if !matches!(print, PrintCall::Host) {
+		return Err(CompileError::Unsupported {
+			op_index: i,
+			op: ops[i],
+			reason: "needs the interpreter, which can't be bundled",
+		});
+	}
+	let absolute = cfg.jump_mode == JumpMode::Absolute;
+	dynasm!(jit
+		; mov rdi, rbx
+		; mov rdx, QWORD i as _
+		; mov rcx, QWORD &ops[i] as *const Op as _
+		; mov r8d, absolute as _
+		; mov r9d, cfg.print as _
+		; mov rax, QWORD fallback as *const () as _
+		; call rax
+	);
+	Ok(())
+}
+
+/// Emit the code [`emit_fallback`] jumps to, which continues execution at the op whose
+/// index is in `rax`.
+///
+/// Indices beyond the last op continue at `end`.
+fn emit_dispatch(jit: &mut Assembler<X64Relocation>, labels: &[DynamicLabel], end: DynamicLabel) {
+	dynasm!(jit
+		; ->dispatch:
+		; cmp rax, labels.len() as _
+		; jae =>end
+		; lea rcx, [->table]
+		; lea rax, [rax + rax * 4]
+		; add rax, rcx
+		; jmp rax
+		; ->table:
+	);
+	// Each jump is 5 bytes.
+	for &l in labels {
+		dynasm!(jit ; jmp =>l);
+	}
+}
+
+/// Code of several programs linked by [`link`].
+pub struct Linked {
+	pub code: Vec<u8>,
+	/// The offset in the code at which each program starts.
+	pub offsets: Vec<usize>,
+	/// Like [`JitProgram::print_relocation`], for all programs at once.
+	pub print_relocation: Option<usize>,
+}
+
+/// Put the code of programs compiled with [`PrintCall::External`] one after the other, with
+/// each program's calls to `print` going to a single implementation after them. It is
+/// [`Bundled`](PrintCall::Bundled) if `bundle` is set and external otherwise.
+///
+/// ```
+/// use dynasm_experiments::{jit, jit2, link, parse, Config, PrintCall};
+///
+/// let cfg = Config::default();
+/// let (hi, _) = parse(b"0`+72 0`+33".to_vec());
+/// let (copy, _) = parse(b"1`+5 2`1".to_vec());
+/// let programs = [
+///     jit(hi, &cfg, PrintCall::External).unwrap(),
+///     jit2(copy, &cfg, PrintCall::External).unwrap(),
+/// ];
+/// let linked = link(&programs, true);
+/// assert_eq!(linked.offsets[0], 0);
+/// assert_eq!(linked.offsets[1] % 16, 0);
+/// assert_eq!(linked.print_relocation, None);
+/// let start = linked.offsets[1];
+/// let code = programs[1].code();
+/// let stub = programs[1].print_relocation().unwrap() - 1;
+/// assert_eq!(linked.code[start..][..stub], code[..stub]);
+///
+/// assert!(link(&programs, false).print_relocation.is_some());
+/// ```
+pub fn link(programs: &[JitProgram], bundle: bool) -> Linked {
+	// Pad between functions with int3.
+	let pad = |code: &mut Vec<u8>| code.resize(code.len().next_multiple_of(16), 0xcc);
+	let mut code = Vec::new();
+	let mut offsets = Vec::with_capacity(programs.len());
+	for p in programs {
+		pad(&mut code);
+		offsets.push(code.len());
+		code.extend_from_slice(p.code());
+	}
+	pad(&mut code);
+	let runtime = code.len();
+	let print = if bundle {
+		PrintCall::Bundled
+	} else {
+		PrintCall::External
+	};
+	let mut jit = Assembler::<X64Relocation>::new().unwrap();
+	let relocation = print.emit_runtime(&mut jit);
+	code.extend_from_slice(&jit.finalize().unwrap());
+	// Each program ends with a jump to print, which now goes to the shared implementation.
+	for (p, &start) in programs.iter().zip(&offsets) {
+		let at = start
+			+ p.print_relocation()
+				.expect("not compiled with PrintCall::External");
+		let d = (runtime as isize - (at + 4) as isize) as i32;
+		code[at..at + 4].copy_from_slice(&d.to_le_bytes());
+	}
+	Linked {
+		code,
+		offsets,
+		print_relocation: relocation.map(|r| runtime + r),
+	}
+}
+
+/// Simple JIT which translates each op on its own.
+///
+/// # Example
+///
+/// Jumps to ops that do not exist are rejected:
+///
+/// ```
+/// use dynasm_experiments::{jit, parse, CompileError, Config, PrintCall};
+///
+/// let (ops, _) = parse(b"1`+1 +0`+-2".to_vec());
+/// let e = jit(ops, &Config::default(), PrintCall::Host).err().unwrap();
+/// assert!(matches!(e, CompileError::JumpOutOfRange { op_index: 1, computed_target: -1 }));
+///
+/// let (ops, _) = parse(b"+0`+9223372036854775807".to_vec());
+/// let e = jit(ops, &Config::default(), PrintCall::Host).err().unwrap();
+/// assert!(matches!(e, CompileError::JumpOutOfRange { op_index: 0, .. }));
+/// ```
+///
+/// Like in the interpreters, jumping or calling just past the last op ends the program:
+///
+/// ```
+/// use dynasm_experiments::{capture, jit, jit2, parse, run, run_checked, Config, PrintCall};
+///
+/// let cfg = Config { tape_len: 1, ..Config::default() };
+/// for (code, out) in [("0`+65 +0`+2 0`+1", b"A"), ("0`+66 >2 0`+1", b"B")] {
+///     let (ops, _) = parse(code.as_bytes().to_vec());
+///     assert_eq!(capture(|| run(ops.clone(), &cfg)).1, out);
+///     let (tape, printed) = capture(|| run_checked(ops.clone(), &cfg).unwrap());
+///     assert_eq!(printed, out);
+///     for compile in [jit, jit2] {
+///         let program = compile(ops.clone(), &cfg, PrintCall::Host).unwrap();
+///         assert_eq!(program.execute_captured(&cfg), (out.to_vec(), tape.clone()));
+///     }
+/// }
+/// ```
+///
+/// Cells too far from cell 0 for a 32-bit displacement are addressed with `lea`:
+///
+/// ```
+/// use dynasm_experiments::{jit, jit2, parse, Config, PrintCall};
+///
+/// let far = 1 << 28;
+/// let (ops, _) = parse(format!("{far}`+70 1`+2 {far}`1").into_bytes());
+/// for compile in [jit, jit2] {
+///     let program = compile(ops.clone(), &Config::default(), PrintCall::Host).unwrap();
+///     let mut tape = vec![0; far as usize + 1];
+///     // SAFETY: the program only accesses cells 1 and far and program outlives f.
+///     let f = unsafe { program.as_raw_fn() };
+///     f(tape.as_mut_ptr());
+///     assert_eq!((tape[1], tape[far as usize]), (2, 72));
+/// }
+/// ```
+pub fn jit(ops: Vec<Op>, cfg: &Config, print: PrintCall) -> Result<JitProgram, CompileError> {
+	let ops = Box::<[Op]>::from(ops);
+	let mut jit = dynasmrt::x64::Assembler::new().unwrap();
+	let labels = core::iter::repeat_with(|| jit.new_dynamic_label())
+		.take(ops.len())
+		.collect::<Box<_>>();
+	let end = jit.new_dynamic_label();
+	// r12 holds the amount of calls that may still be nested and r13 the stack pointer outside
+	// of any call.
+	dynasm!(jit
+		; push rbx
+		; push r12
+		; push r13
+		; mov rbx, rdi
+	);
+	if cfg.cycles {
+		// A slot for the counter that keeps the stack aligned.
+		dynasm!(jit ; sub rsp, 16);
+		emit_rdtscp(&mut jit);
+		dynasm!(jit ; mov [rsp], rax);
+	}
+	dynasm!(jit
+		; mov rdi, QWORD cfg.init_v as _
+		; mov r12, QWORD cfg.call_depth as _
+		; mov r13, rsp
+	);
+	if cfg.entry != 0 {
+		dynasm!(jit ; jmp =>labels.get(cfg.entry).copied().unwrap_or(end));
+	}
+	let mut op_offsets = Vec::with_capacity(ops.len() + 1);
+	let mut branches = Vec::new();
+	for (i, (&op, &lbl)) in ops.iter().zip(labels.iter()).enumerate() {
+		op_offsets.push(jit.offset().0);
+		let lbl = bounded_label(&mut jit, cfg, lbl, end);
+		match op {
+			Op::SetV(a, b) => {
+				dynasm!(jit ; =>lbl ; mov rdi, QWORD b as _);
+				match cell(&mut jit, a) {
+					Some(d) => dynasm!(jit ; add rdi, [rbx + d] ; mov [rbx + d], rdi),
+					None => dynasm!(jit ; add rdi, [rax] ; mov [rax], rdi),
+				}
+				(a == 0 && cfg.print).then(|| {
+					print.emit(&mut jit);
+					dynasm!(jit ; mov rdi, [rbx]);
+				});
+			}
+			Op::SetA(a, b) => {
+				dynasm!(jit ; =>lbl);
+				match cell(&mut jit, b) {
+					Some(d) => dynasm!(jit ; mov rdi, [rbx + d]),
+					None => dynasm!(jit ; mov rdi, [rax]),
+				}
+				match cell(&mut jit, a) {
+					Some(d) => dynasm!(jit ; add rdi, [rbx + d] ; mov [rbx + d], rdi),
+					None => dynasm!(jit ; add rdi, [rax] ; mov [rax], rdi),
+				}
+				(a == 0 && cfg.print).then(|| {
+					print.emit(&mut jit);
+					dynasm!(jit ; mov rdi, [rbx]);
+				});
+			}
+			Op::JmpV(a, b) => {
+				dynasm!(jit ; =>lbl);
+				emit_cmp_v(&mut jit, Some(Rq::RDI), Rq::RSP, a);
+				let target = jump_target(&labels, end, i, b)?;
+				dynasm!(jit ; jne =>target);
+				branches.push((jit.offset().0, target));
+			}
+			Op::JmpA(..) => {
+				dynasm!(jit ; =>lbl ; mov rsi, rdi);
+				emit_fallback(&mut jit, &ops, i, print, cfg)?;
+				dynasm!(jit ; mov rdi, rdx ; jmp ->dispatch);
+			}
+			Op::Call(b) => {
+				// Keep the stack aligned.
+				dynasm!(jit
+					; =>lbl
+					; sub r12, 1
+					; jb =>end
+					; sub rsp, 8
+				);
+				let target = jump_target(&labels, end, i, b)?;
+				dynasm!(jit ; call =>target);
+				branches.push((jit.offset().0, target));
+				dynasm!(jit ; add rsp, 8);
+			}
+			Op::Ret => {
+				dynasm!(jit
+					; =>lbl
+					; cmp rsp, r13
+					; je =>end
+					; add r12, 1
+					; ret
+				);
+			}
+			Op::ReadInts(_) => {
+				dynasm!(jit ; =>lbl);
+				emit_read_ints(&mut jit, &ops, i, print)?;
+				dynasm!(jit ; mov rdi, rax);
+			}
+			Op::Swap(a, b) => {
+				dynasm!(jit ; =>lbl);
+				if a != b {
+					match cell(&mut jit, b) {
+						Some(d) => dynasm!(jit ; mov rsi, [rbx + d]),
+						None => dynasm!(jit ; mov rsi, [rax]),
+					}
+					match cell(&mut jit, a) {
+						Some(d) => dynasm!(jit ; mov rdx, [rbx + d] ; mov [rbx + d], rsi),
+						None => dynasm!(jit ; mov rdx, [rax] ; mov [rax], rsi),
+					}
+					match cell(&mut jit, b) {
+						Some(d) => dynasm!(jit ; mov [rbx + d], rdx),
+						None => dynasm!(jit ; mov [rax], rdx),
+					}
+				}
+			}
+		}
+	}
+	op_offsets.push(jit.offset().0);
+	dynasm!(jit
+		; =>end
+		; mov rsp, r13
+	);
+	if cfg.cycles {
+		emit_rdtscp(&mut jit);
+		dynasm!(jit ; sub rax, [rsp] ; add rsp, 16);
+	}
+	dynasm!(jit
+		; pop r13
+		; pop r12
+		; pop rbx
+		; ret
+	);
+	if ops.iter().any(needs_fallback) {
+		emit_dispatch(&mut jit, &labels, end);
+	}
+	let print_relocation = print.emit_runtime(&mut jit);
+	let branches = resolve_branches(&jit, &branches);
+	let code = jit.finalize().unwrap();
+	let required_tape_len = required_tape_len(&ops);
+	Ok(JitProgram {
+		code,
+		_ops: ops,
+		print_relocation,
+		spills: 0,
+		op_offsets: op_offsets.into(),
+		branches,
+		required_tape_len,
+		cycles: cfg.cycles,
+	})
+}
+
+/// Tape cells cached in registers by [`jit2`].
+struct RegCache {
+	tape2reg: HashMap<isize, Rq>,
+	reg2tape: HashMap<Rq, isize>,
+	alloc: Box<dyn Allocator>,
+	/// Cells which always use the same register, which is never evicted.
+	pinned: HashMap<isize, Rq>,
+	/// Amount of cells that were evicted.
+	spills: usize,
+	/// The index of the op being compiled, if loads and evictions are logged.
+	log: Option<usize>,
+}
+
+impl RegCache {
+	fn new(regs: usize, cfg: &Config, ops: &[Op]) -> Self {
+		let regs = &[Rq::R12, Rq::R13, Rq::R14, Rq::R15, Rq::RBP][..regs];
+		let mut pins = Vec::new();
+		for &p in &cfg.pins {
+			if !pins.contains(&p) {
+				pins.push(p);
+			}
+		}
+		// Keep a register for the other cells.
+		let max = regs.len() - 1;
+		if pins.len() > max {
+			eprintln!(
+				"warning: {} cells are pinned but at most {} can be, ignoring cells {:?}",
+				pins.len(),
+				max,
+				&pins[max..]
+			);
+			pins.truncate(max);
+		}
+		let (pinned, free) = regs.split_at(pins.len());
+		Self {
+			tape2reg: HashMap::new(),
+			reg2tape: HashMap::new(),
+			alloc: regalloc::allocator(cfg.alloc, free, ops, cfg.alloc_seed),
+			pinned: pins.into_iter().zip(pinned.iter().copied()).collect(),
+			spills: 0,
+			log: cfg.reg_log.then_some(0),
+		}
+	}
+
+	/// Get the register holding cell `t`, loading it if necessary.
+	fn load(&mut self, t: isize, jit: &mut Assembler<X64Relocation>) -> Rq {
+		let pinned = self.pinned.get(&t).copied();
+		if let Some(&r) = self.tape2reg.get(&t) {
+			if pinned.is_none() {
+				self.alloc.used(r);
+			}
+			return r;
+		}
+		let r = pinned.unwrap_or_else(|| {
+			let r = self.alloc.pick(&self.reg2tape);
+			self.alloc.used(r);
+			r
+		});
+		if let Some(i) = self.reg2tape.remove(&r) {
+			self.tape2reg.remove(&i).unwrap();
+			self.spills += 1;
+			if let Some(op) = self.log {
+				eprintln!("op {}: spill cell {} from {:?}", op, i, r);
+			}
+			match cell(jit, i) {
+				Some(d) => dynasm!(jit ; mov [rbx + d], Rq(r.code())),
+				None => dynasm!(jit ; mov [rax], Rq(r.code())),
+			}
+		}
+		if let Some(op) = self.log {
+			eprintln!("op {}: load cell {} into {:?}", op, t, r);
+		}
+		match cell(jit, t) {
+			Some(d) => dynasm!(jit ; mov Rq(r.code()), QWORD [rbx + d]),
+			None => dynasm!(jit ; mov Rq(r.code()), QWORD [rax]),
+		}
+		self.tape2reg.insert(t, r);
+		self.reg2tape.insert(r, t);
+		r
+	}
+
+	/// The cell register `r` holds, if any.
+	fn cell_in(&self, r: Rq) -> Option<isize> {
+		self.reg2tape.get(&r).copied()
+	}
+
+	/// Whether register `r` holds cell `t`.
+	fn holds(&self, r: Rq, t: isize) -> bool {
+		self.tape2reg.get(&t) == Some(&r)
+	}
+
+	/// Forget all cached cells.
+	///
+	/// Cells are written back as soon as they are modified, so nothing needs to be stored.
+	fn clear(&mut self) {
+		self.tape2reg.clear();
+		self.reg2tape.clear();
+	}
+}
+
+/// Add a constant to a register with the shortest encoding.
+///
+/// Immediates are sign-extended, so `sub` is used for constants whose negation fits in fewer
+/// bits, such as 128. Constants that don't fit in 32 bits either way go through rax.
+fn emit_add_imm(jit: &mut Assembler<X64Relocation>, r: Rq, b: isize) {
+	let neg = b.checked_neg();
+	if let Ok(b) = i8::try_from(b) {
+		dynasm!(jit ; add Rq(r.code()), BYTE b);
+	} else if let Some(Ok(n)) = neg.map(i8::try_from) {
+		dynasm!(jit ; sub Rq(r.code()), BYTE n);
+	} else if let Ok(b) = i32::try_from(b) {
+		dynasm!(jit ; add Rq(r.code()), DWORD b);
+	} else if let Some(Ok(n)) = neg.map(i32::try_from) {
+		dynasm!(jit ; sub Rq(r.code()), DWORD n);
+	} else {
+		dynasm!(jit
+			; mov rax, QWORD b as _
+			; add Rq(r.code()), rax
+		);
+	}
+}
+
+/// JIT with heavier optimization
+///
+/// This JIT actually doesn't generate correct code in all cases but w/e.
+///
+/// If any op needs [`fallback`] or the program calls, execution may continue at any op, so V
+/// is kept on the stack and no cells are kept in registers across ops. The same applies to
+/// programs that read input, which may write any cell.
+///
+/// # Example
+///
+/// ```
+/// use dynasm_experiments::{jit2, parse, CompileError, Config, PrintCall};
+///
+/// let (ops, _) = parse(b"1`+1 +0`+2".to_vec());
+/// let e = jit2(ops, &Config::default(), PrintCall::Host).err().unwrap();
+/// assert!(matches!(e, CompileError::JumpOutOfRange { op_index: 1, computed_target: 3 }));
+/// ```
+///
+/// A jump before any op sets V compares with the configured initial V:
+///
+/// ```
+/// use dynasm_experiments::{jit2, parse, Config, PrintCall};
+///
+/// // Op 0 skips op 1 if V differs from 1.
+/// let (ops, _) = parse(b"+1`+2 1`+5 2`+6".to_vec());
+/// for (init_v, expect) in [(1, [0, 5, 6]), (7, [0, 0, 6])] {
+///     let cfg = Config { init_v, ..Config::default() };
+///     let program = jit2(ops.clone(), &cfg, PrintCall::Host).unwrap();
+///     let mut tape = [0; 3];
+///     // SAFETY: the program only accesses cells 1 and 2 and program outlives f.
+///     let f = unsafe { program.as_raw_fn() };
+///     f(tape.as_mut_ptr());
+///     assert_eq!(tape, expect);
+/// }
+/// ```
+///
+/// Constants are added with the shortest encoding for their value:
+///
+/// ```
+/// use dynasm_experiments::{jit2, Config, Op, PrintCall};
+///
+/// let bs = [127, 128, -128, -129, 1 << 31, -(1 << 31), (1 << 31) - 1, -(1 << 31) - 1];
+/// let bs = [&bs[..], &[isize::MAX, isize::MIN]].concat();
+/// let ops = (1..).zip(&bs).map(|(a, &b)| Op::SetV(a, b)).collect::<Vec<_>>();
+/// let program = jit2(ops, &Config::default(), PrintCall::Host).unwrap();
+/// let mut tape = vec![0; bs.len() + 1];
+/// // SAFETY: the program only accesses cells 1 to bs.len() and program outlives f.
+/// let f = unsafe { program.as_raw_fn() };
+/// f(tape.as_mut_ptr());
+/// assert_eq!(tape[1..], bs);
+///
+/// // 128 and 1 << 31 are subtracted as -128 and -(1 << 31), which take as little space as
+/// // adding 127 and (1 << 31) - 1.
+/// let size = |b| {
+///     let ops = vec![Op::SetV(1, b)];
+///     jit2(ops, &Config::default(), PrintCall::Host).unwrap().code().len()
+/// };
+/// assert_eq!(size(128), size(127));
+/// assert_eq!(size(1 << 31), size((1 << 31) - 1));
+/// assert!(size(127) < size(1 << 31));
+/// assert!(size(1 << 31) < size(-(1 << 31) - 1));
+/// ```
+///
+/// Calls use the native stack:
+///
+/// ```
+/// use dynasm_experiments::{flush, jit2, parse, Config, PrintCall};
+///
+/// // Op 4 counts down cell 1 and calls itself until it is zero. Each return except the
+/// // innermost one counts in cell 2.
+/// let (ops, _) = parse(b"9`+2 1`+3 >2 < 1`+-1 +0`9 < >-3 2`+1 <".to_vec());
+/// let program = jit2(ops, &Config::default(), PrintCall::Host).unwrap();
+/// let mut tape = [0; 10];
+/// // SAFETY: the program only accesses cells 1, 2 and 9 and program outlives f.
+/// let f = unsafe { program.as_raw_fn() };
+/// f(tape.as_mut_ptr());
+/// flush();
+/// assert_eq!(tape[1..3], [0, 2]);
+/// ```
+pub fn jit2(ops: Vec<Op>, cfg: &Config, print: PrintCall) -> Result<JitProgram, CompileError> {
+	let ops = Box::<[Op]>::from(ops);
+	let fallback = ops.iter().any(needs_fallback);
+	let calls = ops.iter().any(is_call);
+	// Input may be written to any cell.
+	let input = ops.iter().any(|op| matches!(op, Op::ReadInts(_)));
+	let dynamic = fallback || calls || input;
+	// Calls move the stack pointer, so rbp points to the slots instead.
+	let frame = if calls { Rq::RBP } else { Rq::RSP };
+	let mut jit = dynasmrt::x64::Assembler::new().unwrap();
+	let labels = core::iter::repeat_with(|| jit.new_dynamic_label())
+		.take(ops.len())
+		.collect::<Box<_>>();
+	let entry = jit.new_dynamic_label();
+	let end = jit.new_dynamic_label();
+	dynasm!(jit
+		; push rbx
+		; push rbp
+		; push r15
+		; push r14
+		; push r13
+		; push r12
+		// Keep the stack aligned for calls, which also gives slots for V and the amount of
+		// calls that may still be nested.
+		; sub rsp, 24
+		; mov rax, QWORD cfg.init_v as _
+		; mov [rsp], rax
+		; mov rax, QWORD cfg.call_depth as _
+		; mov [rsp + 8], rax
+		; mov rbx, rdi
+	);
+	if cfg.cycles {
+		emit_rdtscp(&mut jit);
+		dynasm!(jit ; mov [rsp + 16], rax);
+	}
+	if calls {
+		dynasm!(jit ; mov rbp, rsp);
+	}
+	if cfg.entry != 0 {
+		dynasm!(jit ; jmp =>entry);
+	}
+
+	let regs = if calls { cfg.regs.min(4) } else { cfg.regs };
+	let mut cache = RegCache::new(regs, cfg, &ops);
+	let call_print = |jit: &mut Assembler<X64Relocation>, reg: Rq| {
+		dynasm!(jit ; mov rdi, Rq(reg.code()));
+		print.emit(jit);
+	};
+
+	// The register V is in, or `None` if it is only on the stack.
+	let mut last_reg: Option<Rq> = None;
+	// The ops jumps continue at, which are only entered with V on the stack.
+	let mut targets = vec![false; ops.len()];
+	for (i, op) in ops.iter().enumerate() {
+		if let Op::JmpV(_, b) = *op {
+			if let Some(t) = i.checked_add_signed(b).and_then(|t| targets.get_mut(t)) {
+				*t = true;
+			}
+		}
+	}
+
+	let mut op_offsets = Vec::with_capacity(ops.len() + 1);
+	let mut branches = Vec::new();
+	for (i, (&op, &lbl)) in ops.iter().zip(labels.iter()).enumerate() {
+		op_offsets.push(jit.offset().0);
+		if let Some(op) = cache.log.as_mut() {
+			*op = i;
+		}
+		// Jumps to the op may come with other cells in registers, so none are assumed to be.
+		let target = i == cfg.entry || targets[i];
+		if target && !dynamic {
+			if let Some(r) = last_reg.take() {
+				dynasm!(jit ; mov [Rq(frame.code())], Rq(r.code()));
+			}
+		}
+		if i == cfg.entry {
+			dynasm!(jit ; =>entry);
+		}
+		if target || dynamic {
+			cache.clear();
+		}
+		let lbl = bounded_label(&mut jit, cfg, lbl, end);
+		match op {
+			Op::SetV(a, b) => {
+				dynasm!(jit ; =>lbl);
+				let r = cache.load(a, &mut jit);
+				emit_add_imm(&mut jit, r, b);
+				match cell(&mut jit, a) {
+					Some(d) => dynasm!(jit ; mov [rbx + d], Rq(r.code())),
+					None => dynasm!(jit ; mov [rax], Rq(r.code())),
+				}
+				(a == 0 && cfg.print).then(|| call_print(&mut jit, r));
+				if dynamic {
+					dynasm!(jit ; mov [Rq(frame.code())], Rq(r.code()));
+				}
+				last_reg = Some(r);
+			}
+			Op::SetA(a, b) => {
+				dynasm!(jit ; =>lbl);
+				let r = cache.load(a, &mut jit);
+				match cell(&mut jit, b) {
+					Some(d) => dynasm!(jit ; add Rq(r.code()), [rbx + d]),
+					None => dynasm!(jit ; add Rq(r.code()), [rax]),
+				}
+				match cell(&mut jit, a) {
+					Some(d) => dynasm!(jit ; mov [rbx + d], Rq(r.code())),
+					None => dynasm!(jit ; mov [rax], Rq(r.code())),
+				}
+				(a == 0 && cfg.print).then(|| call_print(&mut jit, r));
+				if dynamic {
+					dynasm!(jit ; mov [Rq(frame.code())], Rq(r.code()));
+				}
+				last_reg = Some(r);
+			}
+			Op::JmpV(a, b) if dynamic => {
+				dynasm!(jit ; =>lbl);
+				emit_cmp_v(&mut jit, None, frame, a);
+				let target = jump_target(&labels, end, i, b)?;
+				dynasm!(jit ; jne =>target);
+				branches.push((jit.offset().0, target));
+			}
+			Op::JmpV(a, b) => {
+				dynasm!(jit ; =>lbl);
+				// The target expects V on the stack.
+				if let Some(r) = last_reg {
+					dynasm!(jit ; mov [Rq(frame.code())], Rq(r.code()));
+				}
+				emit_cmp_v(&mut jit, last_reg, frame, a);
+				let target = jump_target(&labels, end, i, b)?;
+				dynasm!(jit ; jne =>target);
+				branches.push((jit.offset().0, target));
+			}
+			Op::JmpA(..) => {
+				dynasm!(jit ; =>lbl ; mov rsi, [Rq(frame.code())]);
+				emit_fallback(&mut jit, &ops, i, print, cfg)?;
+				dynasm!(jit ; mov [Rq(frame.code())], rdx ; jmp ->dispatch);
+			}
+			Op::Call(b) => {
+				dynasm!(jit
+					; =>lbl
+					; sub QWORD [rbp + 8], 1
+					; jb =>end
+					; sub rsp, 8
+				);
+				let target = jump_target(&labels, end, i, b)?;
+				dynasm!(jit ; call =>target);
+				branches.push((jit.offset().0, target));
+				dynasm!(jit ; add rsp, 8);
+			}
+			Op::Ret => {
+				dynasm!(jit
+					; =>lbl
+					; cmp rsp, rbp
+					; je =>end
+					; add QWORD [rbp + 8], 1
+					; ret
+				);
+			}
+			Op::ReadInts(_) => {
+				dynasm!(jit ; =>lbl);
+				emit_read_ints(&mut jit, &ops, i, print)?;
+				dynasm!(jit ; mov [Rq(frame.code())], rax);
+			}
+			Op::Swap(a, b) => {
+				dynasm!(jit ; =>lbl);
+				if a != b {
+					let v_cell = last_reg.and_then(|r| cache.cell_in(r));
+					let ra = cache.load(a, &mut jit);
+					let rb = cache.load(b, &mut jit);
+					// With few registers, loading A or B may evict the cell V is in. It was
+					// written back and the swap hasn't changed it yet, so V is taken from it.
+					if let (Some(r), Some(c)) = (last_reg, v_cell) {
+						if !dynamic && !cache.holds(r, c) {
+							match cell(&mut jit, c) {
+								Some(d) => dynasm!(jit ; mov rdx, [rbx + d]),
+								None => dynasm!(jit ; mov rdx, [rax]),
+							}
+							dynasm!(jit ; mov [Rq(frame.code())], rdx);
+							last_reg = None;
+						}
+					}
+					if cache.holds(ra, a) {
+						// The registers trade values, so V moves along with the cell it is in.
+						dynasm!(jit ; xchg Rq(ra.code()), Rq(rb.code()));
+						for (r, c) in [(ra, a), (rb, b)] {
+							match cell(&mut jit, c) {
+								Some(d) => dynasm!(jit ; mov [rbx + d], Rq(r.code())),
+								None => dynasm!(jit ; mov [rax], Rq(r.code())),
+							}
+						}
+						last_reg = last_reg.map(|r| match r {
+							_ if r == ra => rb,
+							_ if r == rb => ra,
+							r => r,
+						});
+					} else {
+						// Loading cell B evicted cell A, since only one register is free.
+						match cell(&mut jit, a) {
+							Some(d) => {
+								dynasm!(jit ; mov rsi, [rbx + d] ; mov [rbx + d], Rq(rb.code()))
+							}
+							None => dynasm!(jit ; mov rsi, [rax] ; mov [rax], Rq(rb.code())),
+						}
+						dynasm!(jit ; mov Rq(rb.code()), rsi);
+						match cell(&mut jit, b) {
+							Some(d) => dynasm!(jit ; mov [rbx + d], Rq(rb.code())),
+							None => dynasm!(jit ; mov [rax], Rq(rb.code())),
+						}
+					}
+				}
+			}
+		}
+	}
+	op_offsets.push(jit.offset().0);
+	if cfg.entry >= labels.len() {
+		dynasm!(jit ; =>entry);
+	}
+	dynasm!(jit
+		; =>end
+	);
+	if calls {
+		dynasm!(jit ; mov rsp, rbp);
+	}
+	if cfg.cycles {
+		emit_rdtscp(&mut jit);
+		dynasm!(jit ; sub rax, [rsp + 16]);
+	}
+	dynasm!(jit
+		; add rsp, 24
+		; pop r12
+		; pop r13
+		; pop r14
+		; pop r15
+		; pop rbp
+		; pop rbx
+		; ret
+	);
+	if fallback {
+		emit_dispatch(&mut jit, &labels, end);
+	}
+	let print_relocation = print.emit_runtime(&mut jit);
+	let branches = resolve_branches(&jit, &branches);
+	let code = jit.finalize().unwrap();
+	let required_tape_len = required_tape_len(&ops);
+	Ok(JitProgram {
+		code,
+		_ops: ops,
+		print_relocation,
+		spills: cache.spills,
+		op_offsets: op_offsets.into(),
+		branches,
+		required_tape_len,
+		cycles: cfg.cycles,
+	})
+}
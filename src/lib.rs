@@ -0,0 +1,804 @@
+//! # JIT compiler & interpreter for ´
+//!
+//! This program is a quick experiment to (naïvely) test the performance difference
+//! between interpreters and JIT compilers for simple programs.
+//!
+//! ## ´
+//!
+//! ´ is a derivative of [\`]. It is modified such that finite loops are possible.
+//!
+//! Instructions still use backticks since forward ticks are not ASCII and hence annoying.
+//!
+//! ## Instructions
+//!
+//! A and B are integer constants, \[X\] denotes an address dereference,  V is the last assigned
+//! value (0 before any assignment unless set with `--init-v`), P is the instruction pointer.
+//!
+//! | Syntax |       Function      |
+//! | ------ | ------------------- |
+//! | A`+B   | [A] += B, V = [A]   |
+//! | A`B    | [A] += [B], V = [A] |
+//! | +A`+B  | V != A ? P += B     |
+//! | +A`B   | V != A ? P += [B]   |
+//! | >B     | call P + B          |
+//! | <      | return              |
+//! | ,A     | read into [A]..     |
+//! | ~A`B   | swap [A] and [B]    |
+//! | other  | ignored             |
+//!
+//! ## Cells
+//!
+//! Cells are 64-bit two's complement integers and additions wrap around on overflow.
+//!
+//! Signedness is mostly not observable: jumps only compare for equality, which is the same for
+//! signed and unsigned cells, and by default output only uses the low 32 bits of a cell as a
+//! code point. Negative values hence print as U+FFFD.
+//!
+//! ## Output
+//!
+//! `--output` selects how values added to cell 0 are printed.
+//!
+//! | Mode    |                       Output                       |
+//! | ------- | -------------------------------------------------- |
+//! | char    | The low 32 bits as a UTF-8 character, or U+FFFD    |
+//! | byte    | The low 8 bits as a byte, so -1 prints as 0xff     |
+//! | decimal | The signed value in decimal, followed by a newline |
+//!
+//! ## Calls
+//!
+//! `>B` continues at P + B and remembers P + 1, where the matching `<` continues. A `<`
+//! outside of any call ends the program, as does a call nested deeper than the call depth
+//! limit, which is 1024 unless set with `--call-depth`. The checked interpreter reports the
+//! latter as an error.
+//!
+//! ## Input
+//!
+//! `,A` reads a line of whitespace-separated integers from stdin into [A], [A + 1] and so on,
+//! up to the first word that isn't an integer, and sets V to the amount of integers read.
+//! At the end of input no cells are written and V is set to -1.
+//!
+//! ## Comments
+//!
+//! `#` starts a comment that runs until the end of the line.
+//!
+//! With `--strict`, anything outside comments that is neither an instruction nor whitespace,
+//! such as a stray `` ` `` or a `+` without its numbers, is an error instead of being ignored.
+//!
+//! Comments of the form `#!<name> <value>` before the first instruction are directives,
+//! which set options the program needs. Command line flags take precedence. Unrecognized
+//! directives are treated as ordinary comments.
+//!
+//! | Directive  |             Function             |
+//! | ---------- | -------------------------------- |
+//! | #!tape N   | Use a tape of N cells            |
+//! | #!entry N  | Start execution at instruction N |
+//! | #!pin A    | Keep cell A in a register        |
+//!
+//! ## Data
+//!
+//! A `%%` outside a comment ends the instructions. The bytes after the end of its line are data
+//! that is on the tape when the program starts: byte N of the data is in cell N + 1, which
+//! leaves cell 0 for output, and all other cells are 0. The tape must be large enough for the
+//! data. Compiled code written with `--emit-shared` or `--emit-object` doesn't include it.
+//! `examples/data.ft` prints its data with each byte increased by one.
+//!
+//! [\`]: https://esolangs.org/wiki/%60
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub mod analyze;
+pub mod cell;
+pub mod disasm;
+pub mod elf;
+pub mod gen;
+mod interpreter;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod opt;
+mod parse;
+pub mod perf;
+#[cfg(feature = "image")]
+pub mod png;
+mod regalloc;
+#[cfg(feature = "jit")]
+mod tape;
+pub mod trace;
+
+pub use interpreter::{run, run_checked, run_with, Checked, RuntimeError, Snapshot};
+#[cfg(feature = "jit")]
+pub use jit::{jit, jit2, link, CompileError, JitProgram, Linked, PrintCall};
+pub use parse::{
+	parse, parse_strict, parse_with_spans, ParseError, Parsed, ProgramOptions, DATA_MARKER,
+};
+pub use regalloc::Alloc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+	SetV(isize, isize),
+	SetA(isize, isize),
+	JmpV(isize, isize),
+	JmpA(isize, isize),
+	Call(isize),
+	Ret,
+	ReadInts(isize),
+	/// Swap cells A and B. This neither sets V nor prints, even if A or B is cell 0.
+	///
+	/// ```
+	/// use dynasm_experiments::{capture, parse, run, run_checked, Config, Output};
+	///
+	/// // Cells 1 and 2 trade values twice, while swapping cell 3 with itself does nothing.
+	/// let (ops, _) = parse(b"1`+5 2`+7 3`+9 ~1`2 ~3`3 1`+1 ~2`1 0`1 0`2 0`3 ~0`3".to_vec());
+	/// let cfg = Config { tape_len: 4, output: Output::Decimal, ..Config::default() };
+	/// assert_eq!(capture(|| run(ops.clone(), &cfg)).1, b"5\n13\n22\n");
+	/// assert_eq!(run_checked(ops.clone(), &cfg).unwrap(), [9, 5, 8, 22]);
+	///
+	/// // jit2 swaps registers if it can keep both cells in them, which it can't with one.
+	/// #[cfg(feature = "jit")]
+	/// for regs in 1..=5 {
+	///     use dynasm_experiments::{jit, jit2, PrintCall};
+	///
+	///     let cfg = Config { regs, ..cfg.clone() };
+	///     for compile in [jit, jit2] {
+	///         let program = compile(ops.clone(), &cfg, PrintCall::Host).unwrap();
+	///         let (out, tape) = program.execute_captured(&cfg);
+	///         assert_eq!((&out[..], &tape[..]), (&b"5\n13\n22\n"[..], &[9, 5, 8, 22][..]));
+	///     }
+	/// }
+	/// ```
+	Swap(isize, isize),
+}
+
+impl Op {
+	/// The name of the op's variant.
+	pub fn name(self) -> &'static str {
+		match self {
+			Op::SetV(..) => "SetV",
+			Op::SetA(..) => "SetA",
+			Op::JmpV(..) => "JmpV",
+			Op::JmpA(..) => "JmpA",
+			Op::Call(_) => "Call",
+			Op::Ret => "Ret",
+			Op::ReadInts(_) => "ReadInts",
+			Op::Swap(..) => "Swap",
+		}
+	}
+}
+
+/// Formats the op in its canonical source form, which [`parse`] turns back into the same op.
+///
+/// ```
+/// use dynasm_experiments::Op;
+///
+/// let ops = [
+///     (Op::SetV(0, -7), "0`+-7"),
+///     (Op::SetA(isize::MIN, 2), "-9223372036854775808`2"),
+///     (Op::JmpV(-1, isize::MAX), "+-1`+9223372036854775807"),
+///     (Op::JmpA(3, -4), "+3`-4"),
+///     (Op::Call(-2), ">-2"),
+///     (Op::Ret, "<"),
+///     (Op::ReadInts(5), ",5"),
+///     (Op::Swap(6, -6), "~6`-6"),
+/// ];
+/// for (op, s) in ops {
+///     assert_eq!(op.to_string(), s);
+///     assert_eq!(s.parse::<Op>(), Ok(op));
+/// }
+///
+/// assert_eq!("# no ops".parse::<Op>(), Err("no op found"));
+/// assert_eq!("< <".parse::<Op>(), Err("more than one op found"));
+/// ```
+impl fmt::Display for Op {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Op::SetV(a, b) => write!(f, "{}`+{}", a, b),
+			Op::SetA(a, b) => write!(f, "{}`{}", a, b),
+			Op::JmpV(a, b) => write!(f, "+{}`+{}", a, b),
+			Op::JmpA(a, b) => write!(f, "+{}`{}", a, b),
+			Op::Call(b) => write!(f, ">{}", b),
+			Op::Ret => write!(f, "<"),
+			Op::ReadInts(a) => write!(f, ",{}", a),
+			Op::Swap(a, b) => write!(f, "~{}`{}", a, b),
+		}
+	}
+}
+
+impl FromStr for Op {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match &parse(s.as_bytes().to_vec()).0[..] {
+			&[op] => Ok(op),
+			[] => Err("no op found"),
+			_ => Err("more than one op found"),
+		}
+	}
+}
+
+/// Amount of cells on the tape if neither the program nor the user specifies it.
+pub const TAPE_LEN: usize = 0x10000;
+
+/// Maximum amount of nested calls if the user doesn't specify it.
+pub const CALL_DEPTH: usize = 1024;
+
+/// Amount of registers [`jit2`] can allocate tape cells to.
+pub const JIT2_REGS: usize = 5;
+
+/// Settings for running a program.
+#[derive(Clone)]
+pub struct Config {
+	/// Amount of cells on the tape.
+	pub tape_len: usize,
+	/// Values of the first cells before the program starts, like those of
+	/// [`ProgramOptions::initial_tape`]. All other cells start at 0. There must be no more
+	/// values than cells.
+	///
+	/// Code compiled to a shared library or object doesn't include them.
+	///
+	/// ```
+	/// use dynasm_experiments::{capture, parse, run_checked, Config};
+	///
+	/// // Print the data plus one.
+	/// let (ops, options) = parse(b"~0`1 0`+1 ~0`1 ~0`2 0`+1 ~0`2\n%%\nHZ".to_vec());
+	/// let cfg = Config { tape_data: options.initial_tape(), ..Config::default() };
+	/// let (tape, out) = capture(|| run_checked(ops.clone(), &cfg));
+	/// assert_eq!(out, b"I[");
+	/// assert_eq!(tape.unwrap()[..4], [0, 73, 91, 0]);
+	///
+	/// #[cfg(feature = "jit")]
+	/// {
+	///     use dynasm_experiments::{jit, jit2, PrintCall};
+	///     for compile in [jit, jit2] {
+	///         let program = compile(ops.clone(), &cfg, PrintCall::Host).unwrap();
+	///         assert_eq!(program.execute_captured(&cfg).0, b"I[");
+	///     }
+	/// }
+	/// ```
+	pub tape_data: Vec<isize>,
+	/// Index of the first op to execute.
+	pub entry: usize,
+	/// The value of V before any op sets it.
+	pub init_v: isize,
+	/// Amount of registers [`jit2`] may allocate tape cells to. Programs do the same with any
+	/// amount from 1 to [`JIT2_REGS`]:
+	///
+	/// ```
+	/// use dynasm_experiments::{capture, parse, run_checked, Config, Output};
+	///
+	/// // A loop over more cells than there are registers, which swaps cells between setting V
+	/// // and jumping on it.
+	/// let src = "7`+3 5`+9 1`+2 2`1 ~3`2 4`3 0`4 7`+-1 ~1`2 ~5`6 +0`+-8 0`5 0`6";
+	/// let (ops, _) = parse(src.as_bytes().to_vec());
+	/// let cfg = Config { tape_len: 8, output: Output::Decimal, ..Config::default() };
+	/// let (tape, out) = capture(|| run_checked(ops.clone(), &cfg));
+	/// let expect = (out, tape.unwrap());
+	///
+	/// #[cfg(feature = "jit")]
+	/// for regs in 1..=dynasm_experiments::JIT2_REGS {
+	///     use dynasm_experiments::{jit2, PrintCall};
+	///
+	///     let cfg = Config { regs, ..cfg.clone() };
+	///     let program = jit2(ops.clone(), &cfg, PrintCall::Host).unwrap();
+	///     assert_eq!(program.execute_captured(&cfg), expect, "{} registers", regs);
+	/// }
+	/// ```
+	pub regs: usize,
+	/// Whether the checked interpreter warns about cells that are read before being written.
+	pub warn_uninit: bool,
+	/// Whether the checked interpreter reports which ops were never executed.
+	pub coverage: bool,
+	/// A cell and a value at which the checked interpreter halts. The cell is compared after
+	/// each write to it.
+	pub run_until: Option<(isize, isize)>,
+	/// Whether compiled code runs with pages that can't be accessed around the tape, so
+	/// accesses beyond it are reported. Only supported on Unix.
+	pub guard_pages: bool,
+	/// Maximum amount of nested calls. Calls beyond it end the program.
+	pub call_depth: usize,
+	/// How [`jit2`] chooses which register to evict.
+	pub alloc: Alloc,
+	/// Cells [`jit2`] keeps in registers before allocating the remaining registers. At least
+	/// one register is left for other cells, so later pins may be ignored.
+	pub pins: Vec<isize>,
+	/// Whether [`jit2`] prints each cell it loads into or evicts from a register.
+	pub reg_log: bool,
+	/// If set, [`run`] uses a tape of this many cells and maps each address to a cell modulo
+	/// the size, which changes what programs do as different addresses refer to the same
+	/// cell. It is meant for studying the effect of the working set size on performance.
+	pub window: Option<usize>,
+	/// Whether [`run`] executes each run of ops without jumps or calls as a batch, which
+	/// checks the index of the next op once per run instead of once per op.
+	pub batch: bool,
+	/// A file the checked interpreter writes each op it executes to, along with V before it.
+	pub trace: Option<PathBuf>,
+	/// How [`trace`](Self::trace) is written.
+	pub trace_format: trace::Format,
+	/// Whether writes to cell 0 print. Without it no op prints and cell 0 is a cell like any
+	/// other.
+	///
+	/// ```
+	/// use dynasm_experiments::{capture, parse, run, run_checked, Config};
+	///
+	/// let (ops, _) = parse(b"0`+72 1`+5 0`1".to_vec());
+	/// assert_eq!(capture(|| run(ops.clone(), &Config::default())).1, b"HM");
+	///
+	/// let cfg = Config { print: false, ..Config::default() };
+	/// assert_eq!(capture(|| run(ops.clone(), &cfg)).1, b"");
+	/// let (tape, out) = capture(|| run_checked(ops.clone(), &cfg));
+	/// assert_eq!(out, b"");
+	/// assert_eq!(tape.unwrap()[..2], [77, 5]);
+	///
+	/// #[cfg(feature = "jit")]
+	/// {
+	///     use dynasm_experiments::{jit, jit2, PrintCall};
+	///     for compile in [jit, jit2] {
+	///         let program = compile(ops.clone(), &cfg, PrintCall::Host).unwrap();
+	///         let (out, tape) = program.execute_captured(&cfg);
+	///         assert_eq!(out, b"");
+	///         assert_eq!(tape[..2], [77, 5]);
+	///     }
+	/// }
+	/// ```
+	pub print: bool,
+	/// What the cell B of `+A`B` holds.
+	pub jump_mode: JumpMode,
+	/// If set, compiled code counts the ops it executes and ends once it ran this long, so
+	/// programs that never end can be measured.
+	pub timeout: Option<Duration>,
+	/// If set, [`jit2`] shuffles its registers and breaks ties between them randomly with
+	/// this seed, to check that the output doesn't depend on how cells are allocated.
+	pub alloc_seed: Option<u64>,
+	/// Whether runs count cache misses and branch mispredictions, which needs the `perf`
+	/// feature on Linux.
+	pub perf: bool,
+	/// Whether compiled code reads the time stamp counter with `rdtscp` when it starts and
+	/// before it returns, and returns the difference. See [`JitProgram::as_raw_timed_fn`].
+	pub cycles: bool,
+	/// The byte offset in the source of each op, as returned by [`parse_with_spans`], which
+	/// the checked interpreter reports errors with.
+	pub spans: Option<Vec<usize>>,
+	/// If set, the time a program took is printed as a bare number in this unit instead of
+	/// in a human-readable form.
+	pub time_unit: Option<TimeUnit>,
+	/// How printed values are written. Compiled code with a bundled or external `print`
+	/// doesn't use it.
+	pub output: Output,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			tape_len: TAPE_LEN,
+			tape_data: Vec::new(),
+			entry: 0,
+			init_v: 0,
+			regs: JIT2_REGS,
+			warn_uninit: false,
+			coverage: false,
+			run_until: None,
+			guard_pages: false,
+			call_depth: CALL_DEPTH,
+			alloc: Alloc::Fifo,
+			pins: Vec::new(),
+			reg_log: false,
+			window: None,
+			batch: false,
+			trace: None,
+			trace_format: trace::Format::Text,
+			print: true,
+			jump_mode: JumpMode::Relative,
+			timeout: None,
+			alloc_seed: None,
+			perf: false,
+			cycles: false,
+			spans: None,
+			output: Output::Char,
+			time_unit: None,
+		}
+	}
+}
+
+/// A unit to print durations in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+	Ns,
+	Us,
+	Ms,
+	S,
+}
+
+impl TimeUnit {
+	pub const ALL: [Self; 4] = [Self::Ns, Self::Us, Self::Ms, Self::S];
+
+	/// The duration as a number in this unit.
+	///
+	/// ```
+	/// use dynasm_experiments::TimeUnit;
+	/// use std::time::Duration;
+	///
+	/// assert_eq!(TimeUnit::Ns.format(Duration::from_micros(1500)), "1500000");
+	/// assert_eq!(TimeUnit::Ms.format(Duration::from_micros(1500)), "1.5");
+	/// ```
+	pub fn format(self, t: Duration) -> String {
+		match self {
+			Self::Ns => t.as_nanos().to_string(),
+			// Dividing the exact amount of nanoseconds avoids rounding errors in the output.
+			Self::Us => (t.as_nanos() as f64 / 1e3).to_string(),
+			Self::Ms => (t.as_nanos() as f64 / 1e6).to_string(),
+			Self::S => (t.as_nanos() as f64 / 1e9).to_string(),
+		}
+	}
+}
+
+impl fmt::Display for TimeUnit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Ns => "ns",
+			Self::Us => "us",
+			Self::Ms => "ms",
+			Self::S => "s",
+		})
+	}
+}
+
+impl FromStr for TimeUnit {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|u| u.to_string() == s)
+			.ok_or("time unit must be ns, us, ms or s")
+	}
+}
+
+/// What B means in jumps and calls.
+///
+/// The backends only look at the mode for `+A`B`, whose B is only known when it runs. Other
+/// jumps and calls must be made relative with [`to_relative`](Self::to_relative) first.
+///
+/// ```
+/// use dynasm_experiments::{parse, run_checked, Config, JumpMode};
+///
+/// // Op 1 jumps 3 ops ahead to op 4.
+/// let (ops, _) = parse(b"5`+5 +0`+3 2`+1 +0`5 3`+1 4`+1".to_vec());
+/// let cfg = Config { tape_len: 6, ..Config::default() };
+/// let tape = run_checked(ops.clone(), &cfg).unwrap();
+/// assert_eq!(tape[2..5], [0, 1, 1]);
+///
+/// // Op 1 jumps to op 3, which continues at the op in cell 5, which op 0 set to 5.
+/// let cfg = Config { jump_mode: JumpMode::Absolute, ..cfg };
+/// let ops = JumpMode::Absolute.to_relative(ops);
+/// let tape = run_checked(ops, &cfg).unwrap();
+/// assert_eq!(tape[2..5], [0, 0, 1]);
+///
+/// // Compiled code hands op 1 to the interpreter, which continues 4 ops ahead or at op 4.
+/// #[cfg(feature = "jit")]
+/// {
+///     use dynasm_experiments::{jit, PrintCall};
+///
+///     let (ops, _) = parse(b"5`+4 +0`5 2`+1 3`+1 4`+1".to_vec());
+///     let modes = [(JumpMode::Relative, [0, 0, 0]), (JumpMode::Absolute, [0, 0, 1])];
+///     for (jump_mode, expect) in modes {
+///         let cfg = Config { jump_mode, ..cfg.clone() };
+///         let ops = jump_mode.to_relative(ops.clone());
+///         let program = jit(ops, &cfg, PrintCall::Host).unwrap();
+///         assert_eq!(program.execute_captured(&cfg).1[2..5], expect);
+///     }
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JumpMode {
+	/// B is the distance from the jump to the op to continue at.
+	Relative,
+	/// B is the index of the op to continue at.
+	Absolute,
+}
+
+impl JumpMode {
+	pub const ALL: [Self; 2] = [Self::Relative, Self::Absolute];
+
+	/// Rewrite the B of `+A`+B` and `>B` in this mode to a distance. `+A`B` is left as is.
+	///
+	/// Every backend continues at the same ops afterwards:
+	///
+	/// ```
+	/// use dynasm_experiments::{capture, parse, run, run_checked, Config, JumpMode};
+	///
+	/// // Op 1 skips op 2 as V isn't 0. In the second program, op 4 calls op 6, which prints
+	/// // again, and the `<` at op 5 ends it.
+	/// let programs = [
+	///     ("1`+1 +0`+2 0`+66 0`+65", "1`+1 +0`+3 0`+66 0`+65", "A"),
+	///     ("1`+1 +0`+2 0`+66 0`+65 >2 < 0`+1 <", "1`+1 +0`+3 0`+66 0`+65 >6 < 0`+1 <", "AB"),
+	/// ];
+	/// for (relative, absolute, expect) in programs {
+	///     for (jump_mode, src) in [(JumpMode::Relative, relative), (JumpMode::Absolute, absolute)] {
+	///         let ops = jump_mode.to_relative(parse(src.as_bytes().to_vec()).0);
+	///         let cfg = Config { tape_len: 2, jump_mode, ..Config::default() };
+	///         assert_eq!(capture(|| run(ops.clone(), &cfg)).1, expect.as_bytes());
+	///         let (tape, out) = capture(|| run_checked(ops.clone(), &cfg));
+	///         assert_eq!((tape.is_ok(), &out[..]), (true, expect.as_bytes()));
+	///         #[cfg(feature = "jit")]
+	///         for compile in [dynasm_experiments::jit, dynasm_experiments::jit2] {
+	///             let program = compile(ops.clone(), &cfg, dynasm_experiments::PrintCall::Host);
+	///             assert_eq!(program.unwrap().execute_captured(&cfg).0, expect.as_bytes());
+	///         }
+	///     }
+	/// }
+	/// ```
+	pub fn to_relative(self, ops: Vec<Op>) -> Vec<Op> {
+		if self == Self::Relative {
+			return ops;
+		}
+		let ops = ops.into_iter().enumerate();
+		ops.map(|(i, op)| match op {
+			Op::JmpV(a, b) => Op::JmpV(a, b.wrapping_sub(i as isize)),
+			Op::Call(b) => Op::Call(b.wrapping_sub(i as isize)),
+			op => op,
+		})
+		.collect()
+	}
+}
+
+impl fmt::Display for JumpMode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Relative => "relative",
+			Self::Absolute => "absolute",
+		})
+	}
+}
+
+impl FromStr for JumpMode {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|m| m.to_string() == s)
+			.ok_or("jump mode must be relative or absolute")
+	}
+}
+
+/// Print how long a program took to stderr.
+pub fn report_time(cfg: &Config, t: Duration) {
+	match cfg.time_unit {
+		Some(unit) => eprintln!("{}", unit.format(t)),
+		None => eprintln!("{:?}", t),
+	}
+}
+
+/// How printed values are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+	/// The low 32 bits as a code point in UTF-8. Values that aren't code points, which
+	/// includes all negative values, are written as U+FFFD.
+	Char,
+	/// The low 8 bits of the two's complement value as a byte.
+	Byte,
+	/// The signed value in decimal, followed by a newline. The interpreter prints unsigned
+	/// cells as unsigned.
+	Decimal,
+}
+
+impl Output {
+	pub const ALL: [Self; 3] = [Self::Char, Self::Byte, Self::Decimal];
+
+	/// The most bytes [`Output::encode`] appends for a single value.
+	const MAX_LEN: usize = 21;
+
+	/// Append the bytes for a value to `out`.
+	///
+	/// ```
+	/// use dynasm_experiments::Output;
+	///
+	/// let encode = |output: Output, v| {
+	///     let mut out = Vec::new();
+	///     output.encode(v, &mut out);
+	///     out
+	/// };
+	/// assert_eq!(encode(Output::Char, 'é' as isize), "é".as_bytes());
+	/// assert_eq!(encode(Output::Char, -1), "\u{fffd}".as_bytes());
+	/// assert_eq!(encode(Output::Byte, -1), [0xff]);
+	/// assert_eq!(encode(Output::Byte, -256), [0]);
+	/// assert_eq!(encode(Output::Byte, 0x141), [0x41]);
+	/// assert_eq!(encode(Output::Decimal, -42), b"-42\n");
+	/// assert_eq!(encode(Output::Decimal, isize::MIN), b"-9223372036854775808\n");
+	/// ```
+	pub fn encode(self, v: isize, out: &mut Vec<u8>) {
+		match self {
+			Self::Char => {
+				let mut c = [0; 4];
+				let c = char::from_u32(v as u32)
+					.unwrap_or('\u{fffd}')
+					.encode_utf8(&mut c);
+				out.extend_from_slice(c.as_bytes());
+			}
+			Self::Byte => out.push(v as u8),
+			Self::Decimal => {
+				use std::io::Write;
+				writeln!(out, "{}", v).unwrap();
+			}
+		}
+	}
+}
+
+impl fmt::Display for Output {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Char => "char",
+			Self::Byte => "byte",
+			Self::Decimal => "decimal",
+		})
+	}
+}
+
+impl FromStr for Output {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|o| o.to_string() == s)
+			.ok_or("output must be char, byte or decimal")
+	}
+}
+
+/// Maximum amount of output bytes kept in memory before they are written to stdout.
+const OUTPUT_BUFFER_SIZE: usize = 8 * 1024;
+
+thread_local! {
+	static OUTPUT: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(OUTPUT_BUFFER_SIZE));
+	static OUTPUT_MODE: Cell<Output> = const { Cell::new(Output::Char) };
+	/// Output written out while capturing, instead of writing it to stdout.
+	static CAPTURED: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Call `f` and return what it printed on this thread along with its result.
+pub fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<u8>) {
+	flush();
+	CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+	let r = f();
+	flush();
+	(r, CAPTURED.with(|c| c.borrow_mut().take()).unwrap())
+}
+
+/// A 64-bit FNV-1a hash of what a program printed and the tape it left, for comparing runs
+/// without keeping either around.
+///
+/// ```
+/// use dynasm_experiments::checksum;
+///
+/// let sum = checksum(b"Hi", &[72, 105, 0]);
+/// assert_eq!(sum, checksum(b"Hi", &[72, 105, 0]));
+/// assert_ne!(sum, checksum(b"Hi", &[72, 105, 1]));
+/// assert_ne!(sum, checksum(b"Hj", &[72, 105, 0]));
+/// // Output isn't confused with the start of the tape.
+/// assert_ne!(checksum(b"", &[1]), checksum(&1isize.to_le_bytes(), &[]));
+/// ```
+pub fn checksum(output: &[u8], tape: &[isize]) -> u64 {
+	let len = (output.len() as u64).to_le_bytes();
+	let cells = tape.iter().flat_map(|c| c.to_le_bytes());
+	let bytes = len.into_iter().chain(output.iter().copied()).chain(cells);
+	bytes.fold(0xcbf2_9ce4_8422_2325, |h, b| {
+		(h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+	})
+}
+
+/// Set how [`print`] writes values on this thread.
+pub(crate) fn set_output(output: Output) {
+	OUTPUT_MODE.with(|m| m.set(output));
+}
+
+pub(crate) extern "C" fn print(v: isize) {
+	print_cell(v)
+}
+
+/// Print a cell of the interpreter, which [`Output::Decimal`] writes as its own type does.
+pub(crate) fn print_cell<C: cell::Cell>(c: C) {
+	let mode = OUTPUT_MODE.with(Cell::get);
+	OUTPUT.with(|out| {
+		let mut out = out.borrow_mut();
+		if out.len() + Output::MAX_LEN > OUTPUT_BUFFER_SIZE {
+			write_output(&mut out);
+		}
+		match mode {
+			Output::Decimal => c.fmt_decimal(&mut out),
+			_ => mode.encode(c.to_isize(), &mut out),
+		}
+	});
+}
+
+/// Parse whitespace-separated integers up to the first word that isn't one, as
+/// [`Op::ReadInts`] does with a line of input.
+///
+/// ```
+/// use dynasm_experiments::{parse_ints, Output};
+///
+/// let ints = parse_ints("1 -2\t3 x 4\n");
+/// assert_eq!(ints, [1, -2, 3]);
+/// let mut out = Vec::new();
+/// ints.iter().for_each(|&n| Output::Decimal.encode(n, &mut out));
+/// assert_eq!(out, b"1\n-2\n3\n");
+/// ```
+pub fn parse_ints(line: &str) -> Vec<isize> {
+	line.split_whitespace()
+		.map_while(|w| w.parse().ok())
+		.collect()
+}
+
+/// Read a line of integers from stdin, or `None` at the end of input.
+///
+/// Input that isn't valid UTF-8 is treated as the end of input.
+pub(crate) fn read_line() -> Option<Vec<isize>> {
+	// Show any prompt before waiting.
+	flush();
+	let mut line = String::new();
+	match std::io::stdin().read_line(&mut line) {
+		Ok(0) | Err(_) => None,
+		Ok(_) => Some(parse_ints(&line)),
+	}
+}
+
+/// Read a line of integers into the cells starting at `start` for compiled code. Returns the
+/// amount of integers or -1 at the end of input.
+///
+/// # Safety
+///
+/// The tape must have a cell for each integer, which compiled code doesn't check like any
+/// other access.
+#[cfg(feature = "jit")]
+pub(crate) unsafe extern "C" fn read_ints(tape: *mut isize, start: isize) -> isize {
+	let Some(ints) = read_line() else {
+		return -1;
+	};
+	for (i, &n) in ints.iter().enumerate() {
+		*tape.offset(start.wrapping_add(i as isize)) = n;
+	}
+	ints.len() as isize
+}
+
+/// Write out any output that is still buffered.
+///
+/// Output is also written out whenever 8 KiB of it are buffered, so programs that print more
+/// than that print all of it in order:
+///
+/// ```
+/// use dynasm_experiments::{capture, parse, run, run_checked, Config, Output};
+///
+/// // Print the numbers from 1 to 3000, which takes 13893 bytes.
+/// let (ops, _) = parse(b"0`+1 +3000`+-1".to_vec());
+/// let expect = (1..=3000).map(|n| format!("{}\n", n)).collect::<String>();
+/// assert!(expect.len() > 8192);
+/// let cfg = Config { tape_len: 1, output: Output::Decimal, ..Config::default() };
+/// assert_eq!(capture(|| run(ops.clone(), &cfg)).1, expect.as_bytes());
+/// assert_eq!(capture(|| run_checked(ops.clone(), &cfg)).1, expect.as_bytes());
+///
+/// #[cfg(feature = "jit")]
+/// for compile in [dynasm_experiments::jit, dynasm_experiments::jit2] {
+///     let program = compile(ops.clone(), &cfg, dynasm_experiments::PrintCall::Host).unwrap();
+///     assert_eq!(program.execute_captured(&cfg).0, expect.as_bytes());
+/// }
+/// ```
+pub fn flush() {
+	OUTPUT.with(|out| write_output(&mut out.borrow_mut()));
+}
+
+fn write_output(out: &mut Vec<u8>) {
+	use std::io::Write;
+	let captured = CAPTURED.with(|c| {
+		let c = c.borrow_mut().as_mut().map(|c| c.extend_from_slice(out));
+		c.is_some()
+	});
+	if captured {
+		out.clear();
+		return;
+	}
+	let mut stdout = std::io::stdout().lock();
+	let _ = stdout.write_all(out);
+	let _ = stdout.flush();
+	out.clear();
+}
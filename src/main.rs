@@ -1,313 +1,1004 @@
-//! # JIT compiler & interpreter for ´
-//!
-//! This program is a quick experiment to (naïvely) test the performance difference
-//! between interpreters and JIT compilers for simple programs.
-//!
-//! ## ´
-//!
-//! ´ is a derivative of [\`]. It is modified such that finite loops are possible.
-//!
-//! Instructions still use backticks since forward ticks are not ASCII and hence annoying.
-//!
-//! ## Instructions
-//!
-//! A and B are integer constants, \[X\] denotes an address dereference,  V is the last assigned
-//! value, P is the instruction pointer.
-//!
-//! | Syntax |       Function      |
-//! | ------ | ------------------- |
-//! | A`+B   | [A] += B, V = [A]   |
-//! | A`B    | [A] += [B], V = [A] |
-//! | +A`+B  | [A] == V ? P += B   |
-//! | +A`B   | [A] == V ? P += [B] |
-//! | other  | ignored             |
-//!
-//! [\`]: https://esolangs.org/wiki/%60
+use dynasm_experiments::{
+	analyze, disasm, gen, opt, parse, parse_with_spans, perf, run, run_checked, run_with, trace,
+	Alloc, Config, JumpMode, Op, Output, TimeUnit, CALL_DEPTH, JIT2_REGS, TAPE_LEN,
+};
+#[cfg(feature = "jit")]
+use dynasm_experiments::{
+	capture, checksum, elf, flush, jit, jit2, link, report_time, Checked, CompileError, JitProgram,
+	PrintCall, RuntimeError,
+};
+use std::num::Saturating;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
 
-use dynasmrt::{dynasm, DynasmApi, DynasmLabelApi};
-use std::time::Instant;
-
-pub enum Op {
-	SetV(isize, isize),
-	SetA(isize, isize),
-	JmpV(isize, isize),
-	JmpA(isize, isize),
+/// Measure how fast [`parse`] processes the given source.
+fn bench_parse(code: Vec<u8>) {
+	let (len, mut ops) = (code.len(), 0);
+	let mut best = Duration::MAX;
+	for _ in 0..5 {
+		let code = code.clone();
+		let t = Instant::now();
+		ops = parse(code).0.len();
+		best = best.min(Instant::now() - t);
+	}
+	let mbs = len as f64 / best.as_secs_f64() / 1e6;
+	println!("{} bytes, {} ops in {:?}: {:.1} MB/s", len, ops, best, mbs);
 }
 
-fn parse_int(code: &mut Vec<u8>) -> Result<isize, ()> {
-	let c = code.pop();
-	let inv = if c == Some(b'-') {
-		true
-	} else {
-		c.map(|c| code.push(c));
-		false
-	};
-	let mut n = 0;
-	while let Some(c) = code.pop() {
-		if c < b'0' || b'9' < c {
-			code.push(c);
-			return Ok(if inv { -n } else { n });
+/// Check that rendering random programs and parsing them again gives back the same ops.
+fn fuzz_parse(iterations: usize, seed: u64) -> Result<(), &'static str> {
+	let mut rng = gen::Rng::new(seed);
+	for _ in 0..iterations {
+		let ops = (0..rng.below(16))
+			.map(|_| gen::op(&mut rng))
+			.collect::<Vec<_>>();
+		let mut code = String::new();
+		for op in &ops {
+			code += &op.to_string();
+			code += [" ", "\n", "\t", " ; ", "\n# comment\n"][rng.below(5) as usize];
+		}
+		let (parsed, _) = parse(code.clone().into_bytes());
+		if parsed != ops {
+			eprintln!("source: {:?}", code);
+			eprintln!("expected: {:?}", ops);
+			eprintln!("got: {:?}", parsed);
+			return Err("ops differ after parsing");
 		}
-		n *= 10;
-		n += (c - b'0') as isize;
 	}
-	Ok(if inv { -n } else { n })
+	println!("{} programs round tripped", iterations);
+	Ok(())
 }
 
-fn parse(mut code: Vec<u8>) -> Vec<Op> {
-	let mut ops = Vec::new();
-	code.reverse();
-	while let Some(b) = code.pop() {
-		match b {
-			b'+' => {
-				if let Ok(a) = parse_int(&mut code) {
-					if code.pop() != Some(b'`') {
-						continue;
-					}
-					let chr = code.pop();
-					let addr = chr != Some(b'+');
-					if addr {
-						chr.map(|c| code.push(c));
-					}
-					if let Ok(b) = parse_int(&mut code) {
-						ops.push(if addr { Op::JmpA(a, b) } else { Op::JmpV(a, b) });
-					}
-				}
-			}
-			_ if b'-' == b || b'0' <= b && b <= b'9' => {
-				code.push(b);
-				if let Ok(a) = parse_int(&mut code) {
-					if code.pop() != Some(b'`') {
-						continue;
-					}
-					let chr = code.pop();
-					let addr = chr != Some(b'+');
-					if addr {
-						chr.map(|c| code.push(c));
-					}
-					if let Ok(b) = parse_int(&mut code) {
-						ops.push(if addr { Op::SetA(a, b) } else { Op::SetV(a, b) });
-					}
-				}
+/// Most steps a program of [`check_passes`] may take before it is skipped.
+const CHECK_STEPS: u64 = 100_000;
+
+/// Check that each optimization pass leaves random programs doing the same in the checked
+/// interpreter, on an empty tape and on one with random values in the cells they use.
+///
+/// Programs that don't finish within [`CHECK_STEPS`] can't be compared and count as the
+/// same. The first program that a pass changes is printed along with the tape.
+fn check_passes(iterations: usize, seed: u64) -> Result<(), Box<dyn std::error::Error>> {
+	let mut rng = gen::Rng::new(seed);
+	let mut runs = 0;
+	for i in 0..iterations {
+		let ops = gen::program(1 + rng.below(64) as usize, rng.next_u64());
+		let random = (0..64).map(|_| rng.below(256) as isize - 128).collect();
+		for tape_data in [Vec::new(), random] {
+			let cfg = Config {
+				tape_len: 64,
+				tape_data,
+				..Config::default()
+			};
+			for &(name, pass) in opt::PASSES {
+				runs += 1;
+				let Some(e) = opt::check_pass(pass, &ops, &cfg, CHECK_STEPS) else {
+					continue;
+				};
+				ops.iter().for_each(|op| eprintln!("{}", op));
+				eprintln!("tape: {:?}", cfg.tape_data);
+				Err(format!(
+					"{} changed what program {} of seed {} does: it {}",
+					name, i, seed, e
+				))?
 			}
-			_ => (),
 		}
 	}
-	ops
+	println!(
+		"{} runs of {} programs did the same after each pass",
+		runs, iterations
+	);
+	Ok(())
 }
 
-extern "C" fn print(v: isize) {
-	use std::io::Write;
-	let mut c = [0; 4];
-	let c = char::from_u32(v as u32)
-		.unwrap_or('\u{fffd}')
-		.encode_utf8(&mut c);
-	let _ = std::io::stdout().write(c.as_bytes());
+/// Compile a program with [`jit2`] using each register allocator and report how they compare.
+#[cfg(feature = "jit")]
+fn compare_alloc(ops: Vec<Op>, cfg: &Config) -> Result<(), CompileError> {
+	let mut results = Vec::new();
+	for alloc in Alloc::ALL {
+		let cfg = Config {
+			alloc,
+			..cfg.clone()
+		};
+		let f = jit2(ops.clone(), &cfg, PrintCall::Host)?;
+		let t = f.execute(&cfg);
+		results.push((alloc, f.code().len(), f.spills(), t));
+	}
+	eprintln!(
+		"{:<6} {:>9} {:>7} {:>12}",
+		"alloc", "code size", "spills", "time"
+	);
+	for (alloc, size, spills, t) in results {
+		eprintln!("{:<6} {:>9} {:>7} {:>12?}", alloc, size, spills, t);
+	}
+	Ok(())
 }
 
-fn run(ops: Vec<Op>) {
-	let ops = &ops[..]; // This is faster. Don't ask me why.
-	let mut i = 0;
-	let mut tape = [0; 0x10000];
-	let mut v = 0;
-	let t = Instant::now();
-	unsafe {
-		while let Some(op) = ops.get(i) {
-			i += 1;
-			let (a, b) = match op {
-				&Op::SetV(a, b) => (a, b),
-				&Op::SetA(a, b) => (a, *tape.get_unchecked(b as usize)),
-				&Op::JmpV(a, b) => {
-					if a != v {
-						i += b as usize - 1
-					}
-					continue;
-				}
-				&Op::JmpA(a, b) => {
-					if a != v {
-						i += *tape.get_unchecked(b as usize) as usize - 1
-					}
-					continue;
-				}
-			};
-			*tape.get_unchecked_mut(a as usize) += b;
-			v = *tape.get_unchecked(a as usize);
-			(a == 0).then(|| print(v));
+/// Measure the code both JITs generate for a single basic block by calling it repeatedly.
+///
+/// A jump, call or return at the end of the block is left out. Prints in the block are
+/// executed each time.
+#[cfg(feature = "jit")]
+fn bench_block(
+	ops: Vec<Op>,
+	cfg: &Config,
+	block: usize,
+	iterations: u32,
+	syntax: disasm::Syntax,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let blocks = analyze::basic_blocks(&ops, cfg.entry);
+	let range = blocks.get(block).ok_or("no such block")?.clone();
+	let mut ops = ops[range.clone()].to_vec();
+	if let Some(Op::JmpV(..) | Op::JmpA(..) | Op::Call(_) | Op::Ret) = ops.last() {
+		ops.pop();
+	}
+	let cfg = Config {
+		entry: 0,
+		..cfg.clone()
+	};
+	let programs = [
+		("jit", jit(ops.clone(), &cfg, PrintCall::Host)?),
+		("jit2", jit2(ops.clone(), &cfg, PrintCall::Host)?),
+	];
+	for (name, f) in programs {
+		let required = f
+			.required_tape_len()
+			.ok_or("the block may access cells outside of any tape")?
+			.max(1);
+		if cfg.tape_len < required {
+			let len = cfg.tape_len;
+			Err(RuntimeError::TapeTooSmall { len, required })?
+		}
+		println!("{} ({} bytes):", name, f.code().len());
+		print!("{}", disasm::disassemble(f.code(), syntax)?);
+		let mut tape = vec![0; cfg.tape_len];
+		// SAFETY: the tape has every cell the code accesses.
+		let run = unsafe { f.as_raw_fn() };
+		let t = Instant::now();
+		for _ in 0..iterations {
+			run(tape.as_mut_ptr());
 		}
+		let t = Instant::now() - t;
+		flush();
+		eprintln!(
+			"{}: block {:?}, {} ops, {:?} per call",
+			name,
+			range,
+			ops.len(),
+			t / iterations
+		);
 	}
-	eprintln!("{:?}", Instant::now() - t);
+	Ok(())
 }
 
-fn jit(ops: Vec<Op>) {
-	let mut jit = dynasmrt::x64::Assembler::new().unwrap();
-	let labels = core::iter::repeat_with(|| jit.new_dynamic_label())
-		.take(ops.len())
-		.collect::<Box<_>>();
-	dynasm!(jit
-		; push rbx
-		; mov rbx, rdi
+/// Print the disassembly of the code of both JITs one after the other and compare their size.
+///
+/// `print` is called through a stub unless the program needs the interpreter, so the
+/// listings only contain addresses that differ between runs in that case.
+#[cfg(feature = "jit")]
+fn dump_both(
+	ops: Vec<Op>,
+	cfg: &Config,
+	syntax: disasm::Syntax,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let host = ops
+		.iter()
+		.any(|op| matches!(op, Op::JmpA(..) | Op::ReadInts(_)));
+	let print = if host {
+		PrintCall::Host
+	} else {
+		PrintCall::External
+	};
+	let programs = [
+		("jit", jit(ops.clone(), cfg, print)?),
+		("jit2", jit2(ops, cfg, print)?),
+	];
+	for (name, f) in &programs {
+		println!("# {} ({} bytes)", name, f.code().len());
+		print!("{}", disasm::disassemble(f.code(), syntax)?);
+		println!();
+	}
+	let (a, b) = (programs[0].1.code().len(), programs[1].1.code().len());
+	let change = (b as f64 / a as f64 - 1.0) * 100.0;
+	println!(
+		"# jit2 is {:+} bytes ({:+.1}%) compared to jit",
+		b as isize - a as isize,
+		change
 	);
-	for (i, (op, &lbl)) in ops.into_iter().zip(labels.iter()).enumerate() {
-		match op {
-			Op::SetV(a, b) => {
-				dynasm!(jit
-					; =>lbl
-					; mov rdi, QWORD b.try_into().unwrap()
-					; add rdi, [rbx + (a * 8).try_into().unwrap()]
-					; mov [rbx + (a * 8).try_into().unwrap()], rdi
-				);
-				(a == 0).then(|| dynasm!(jit ; mov rax, QWORD print as _ ; call rax));
-			}
-			Op::SetA(a, b) => {
-				dynasm!(jit
-					; =>lbl
-					; mov rdi, [rbx + (b * 8).try_into().unwrap()]
-					; add rdi, [rbx + (a * 8).try_into().unwrap()]
-					; mov [rbx + (a * 8).try_into().unwrap()], rdi
-				);
-				(a == 0).then(|| dynasm!(jit ; mov rax, QWORD print as _ ; call rax));
-			}
-			Op::JmpV(a, b) => {
-				dynasm!(jit
-					; =>lbl
-					; mov rax, [rbx + (a * 8).try_into().unwrap()]
-					; cmp rdi, rax
-					; jne =>labels[i - b as usize - 2]
-				);
-			}
-			Op::JmpA(_a, _b) => {
-				todo!()
-			}
+	Ok(())
+}
+
+/// Compile two versions of a program with the same backend and print a unified diff of their
+/// listings from [`disasm::listing`].
+///
+/// The code of programs with a `+A`B` or `,A` refers to addresses that change between runs,
+/// so they aren't supported.
+#[cfg(feature = "jit")]
+fn codegen_diff(
+	backend: &str,
+	files: [String; 2],
+	programs: [(Vec<Op>, &Config); 2],
+	syntax: disasm::Syntax,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let mut paths = Vec::new();
+	for (i, (ops, cfg)) in programs.into_iter().enumerate() {
+		if ops
+			.iter()
+			.any(|op| matches!(op, Op::JmpA(..) | Op::ReadInts(_)))
+		{
+			Err(format!(
+				"{}: codegen-diff does not support +A`B or ,A",
+				files[i]
+			))?
 		}
+		let f = match backend {
+			"jit" => jit(ops.clone(), cfg, PrintCall::External)?,
+			"jit2" => jit2(ops.clone(), cfg, PrintCall::External)?,
+			_ => Err("--backend must be jit or jit2")?,
+		};
+		let listing = disasm::listing(f.code(), &ops, f.op_offsets(), syntax)?;
+		let name = format!("dynasm_experiments_{}_{}.s", std::process::id(), i);
+		let path = std::env::temp_dir().join(name);
+		std::fs::write(&path, listing)?;
+		paths.push(path);
 	}
-	dynasm!(jit
-		; pop rbx
-		; ret
-	);
-	let f = jit.finalize().unwrap();
-	std::fs::write("/tmp/jit.out", &f[..]).unwrap();
-	let f: extern "C" fn(*mut isize) =
-		unsafe { core::mem::transmute(f.ptr(dynasmrt::AssemblyOffset(0))) };
-	let t = Instant::now();
-	f([0; 0x10000].as_mut_ptr());
-	eprintln!("{:?}", Instant::now() - t);
+	let out = Command::new("diff")
+		.args(["-u", "--label", &files[0], "--label", &files[1]])
+		.args(&paths)
+		.output();
+	paths.iter().try_for_each(std::fs::remove_file)?;
+	let out = out?;
+	// diff exits with 1 if the files differ and 2 on errors.
+	match out.status.code() {
+		Some(0) => eprintln!("the code is the same"),
+		Some(1) => {
+			use std::io::Write;
+			std::io::stdout().write_all(&out.stdout)?;
+		}
+		_ => Err(String::from_utf8_lossy(&out.stderr).into_owned())?,
+	}
+	Ok(())
 }
 
-/// JIT with heavier optimization
+/// Run compiled code `runs` times and check that it prints the same and leaves the same tape
+/// each time, which it may not if it uses uninitialized registers or memory.
 ///
-/// This JIT actually doesn't generate correct code in all cases but w/e.
-fn jit2(ops: Vec<Op>) {
-	let mut jit = dynasmrt::x64::Assembler::new().unwrap();
-	let labels = core::iter::repeat_with(|| jit.new_dynamic_label())
-		.take(ops.len())
-		.collect::<Box<_>>();
-	dynasm!(jit
-		; push rbx
-		; push rbp
-		; push r15
-		; push r14
-		; push r13
-		; push r12
-		; mov rbx, rdi
+/// Programs that read input get different input in each run.
+#[cfg(feature = "jit")]
+fn verify_deterministic(
+	f: &JitProgram,
+	cfg: &Config,
+	runs: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+	use std::io::Write;
+	let (out, tape) = f.execute_captured(cfg);
+	std::io::stdout().write_all(&out)?;
+	let sum = checksum(&out, &tape);
+	for run in 1..runs {
+		let (o, t) = f.execute_captured(cfg);
+		// Only a run with a different checksum needs to be compared in detail.
+		if checksum(&o, &t) == sum {
+			continue;
+		}
+		if o != out {
+			Err(format!("run {} printed different output than run 0", run))?
+		}
+		if let Some(i) = (0..tape.len()).find(|&i| t[i] != tape[i]) {
+			let e = format!(
+				"run {} left {} in cell {} instead of {}",
+				run, t[i], i, tape[i]
+			);
+			Err(e)?
+		}
+	}
+	eprintln!(
+		"{} runs printed the same and left the same tape, checksum {:016x}",
+		runs, sum
 	);
+	Ok(())
+}
 
-	use dynasmrt::{Register, x64::Rq, x64::X64Relocation, Assembler};
-	let mut tape2reg = std::collections::HashMap::<isize, Rq>::new();
-	let mut reg2tape = std::collections::HashMap::<Rq, isize>::new();
-	let mut regqueue = std::collections::VecDeque::from([Rq::R12, Rq::R13, Rq::R14, Rq::R15, Rq::RBP]);
+/// Amount of ops `--why-slow` shows the code of.
+#[cfg(feature = "jit")]
+const HOT_OPS: usize = 5;
 
-	let mut load_tape = |t, jit: &mut Assembler<X64Relocation>| {
-		if let Some(r) = tape2reg.get(&t) {
-			return *r;
-		}
-		let r = regqueue.pop_front().unwrap();
-		regqueue.push_back(r);
-		if let Some(i) = reg2tape.remove(&r) {
-			tape2reg.remove(&i).unwrap();
-			dynasm!(jit ; mov [rbx + (i * 8).try_into().unwrap()], Rq(r.code()));
-		}
-		dynasm!(jit ; mov Rq(r.code()), QWORD [rbx + (t * 8).try_into().unwrap()]);
-		tape2reg.insert(t, r);
-		reg2tape.insert(r, t);
-		r
+/// Run a program in the checked interpreter to find the ops it executes most often, and print
+/// the code they were compiled to.
+///
+/// What the program prints while profiling is discarded.
+#[cfg(feature = "jit")]
+fn why_slow(
+	ops: &[Op],
+	cfg: &Config,
+	f: &JitProgram,
+	syntax: disasm::Syntax,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let cfg = Config {
+		coverage: true,
+		..cfg.clone()
 	};
-	let call_print = |jit: &mut Assembler<X64Relocation>, reg: Rq| {
-		dynasm!(jit
-			; mov rdi, Rq(reg.code())
-			; mov rax, QWORD print as _
-			; call rax
+	let mut vm = Checked::new(ops, &cfg);
+	capture(|| {
+		while vm.step()? {}
+		Ok::<_, RuntimeError>(())
+	})
+	.0?;
+	let counts = vm.executions().unwrap();
+	let total = counts.iter().sum::<u64>();
+	let mut hot = (0..ops.len())
+		.filter(|&i| counts[i] > 0)
+		.collect::<Vec<_>>();
+	hot.sort_by_key(|&i| (std::cmp::Reverse(counts[i]), i));
+	println!("{} ops executed", total);
+	let offsets = f.op_offsets();
+	for &i in hot.iter().take(HOT_OPS) {
+		let (start, end) = (offsets[i], offsets[i + 1]);
+		let share = counts[i] as f64 / total as f64 * 100.0;
+		println!();
+		println!(
+			"op {} ({}): {} times ({:.1}%), {} bytes",
+			i,
+			ops[i],
+			counts[i],
+			share,
+			end - start
 		);
+		if start < end {
+			print!(
+				"{}",
+				disasm::disassemble_at(&f.code()[start..end], start, syntax)?
+			);
+		}
+	}
+	Ok(())
+}
+
+/// Print each op as its index, its variant, its operands and its source in fixed columns, for
+/// processing with other tools.
+fn print_ops(ops: &[Op]) {
+	use std::io::Write;
+	let mut out = std::io::stdout().lock();
+	for (i, &op) in ops.iter().enumerate() {
+		let (a, b) = match op {
+			Op::SetV(a, b) | Op::SetA(a, b) | Op::JmpV(a, b) | Op::JmpA(a, b) | Op::Swap(a, b) => {
+				(Some(a), Some(b))
+			}
+			Op::Call(b) => (None, Some(b)),
+			Op::Ret => (None, None),
+			Op::ReadInts(a) => (Some(a), None),
+		};
+		let field = |x: Option<isize>| x.map_or("-".to_string(), |x| x.to_string());
+		// Stop quietly if the output is closed, e.g. by head.
+		if writeln!(
+			out,
+			"{:>8}  {:<8}  {:>20}  {:>20}  {}",
+			i,
+			op.name(),
+			field(a),
+			field(b),
+			op
+		)
+		.is_err()
+		{
+			return;
+		}
+	}
+}
+
+/// Run about half of a program's steps in the checked interpreter, continue from the exact
+/// tape, V and next op in each JIT and check that the result is the same as running the
+/// whole program in the interpreter.
+///
+/// The state is only handed off outside of calls, since the JITs keep them on the native
+/// stack.
+#[cfg(feature = "jit")]
+fn handoff(ops: Vec<Op>, cfg: &Config) -> Result<(), Box<dyn std::error::Error>> {
+	if ops.iter().any(|op| matches!(op, Op::ReadInts(_))) {
+		Err("handoff does not support programs that read input")?
+	}
+	let mut vm = Checked::new(&ops, cfg);
+	let (steps, out) = capture(|| {
+		let mut steps = 0u64;
+		while vm.step()? {
+			steps += 1;
+		}
+		Ok::<_, RuntimeError>(steps)
+	});
+	let (steps, tape) = (steps?, vm.tape().to_vec());
+	let sum = checksum(&out, &tape);
+	eprintln!("interpreter: checksum {:016x}", sum);
+
+	let mut vm = Checked::new(&ops, cfg);
+	let (step, before) = capture(|| {
+		let mut step = 0;
+		while step < steps / 2 || !vm.calls().is_empty() {
+			if !vm.step()? {
+				break;
+			}
+			step += 1;
+		}
+		Ok::<_, RuntimeError>(step)
+	});
+	let (step, state) = (step?, vm.snapshot());
+	eprintln!(
+		"handing off after {} of {} steps at op {} with V = {}",
+		step,
+		steps,
+		state.ip(),
+		state.v()
+	);
+	let cfg = Config {
+		entry: state.ip(),
+		init_v: state.v(),
+		..cfg.clone()
 	};
+	let programs = [
+		("jit", jit(ops.clone(), &cfg, PrintCall::Host)?),
+		("jit2", jit2(ops, &cfg, PrintCall::Host)?),
+	];
+	for (name, f) in &programs {
+		let mut t = state.tape().to_vec();
+		let o = [&before[..], &f.execute_on(&cfg, &mut t)?].concat();
+		let s = checksum(&o, &t);
+		if s == sum {
+			eprintln!(
+				"{}: same output and tape as the interpreter, checksum {:016x}",
+				name, s
+			);
+			continue;
+		}
+		if o != out {
+			Err(format!(
+				"{} printed different output than the interpreter",
+				name
+			))?
+		}
+		if let Some(i) = (0..tape.len()).find(|&i| t[i] != tape[i]) {
+			let e = format!(
+				"{} left {} in cell {} instead of {}",
+				name, t[i], i, tape[i]
+			);
+			Err(e)?
+		}
+	}
+	Ok(())
+}
 
-	let mut last_reg = None;
+/// Write the first `width * height` cells of the tape as a PNG to `out`.
+#[cfg(feature = "image")]
+fn dump_image(
+	tape: &[isize],
+	width: u32,
+	height: u32,
+	rgb: bool,
+	out: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+	use dynasm_experiments::png::{image, Color};
+	let color = if rgb { Color::Rgb } else { Color::Gray };
+	let png = image(tape, width, height, color).ok_or("the tape is smaller than the image")?;
+	Ok(std::fs::write(out, png)?)
+}
 
-	for (i, (op, &lbl)) in ops.into_iter().zip(labels.iter()).enumerate() {
-		match op {
-			Op::SetV(a, b) => {
-				dynasm!(jit ; =>lbl);
-				let r = load_tape(a, &mut jit);
-				if let Ok(b) = i8::try_from(b) {
-					dynasm!(jit ; add Rq(r.code()), BYTE b);
-				} else if let Ok(b) = i32::try_from(b) {
-					dynasm!(jit ; add Rq(r.code()), DWORD b);
-				} else {
-					todo!();
+#[cfg(not(feature = "image"))]
+fn dump_image(
+	_: &[isize],
+	_: u32,
+	_: u32,
+	_: bool,
+	_: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+	Err("--dump-image requires the image feature")?
+}
+
+/// Run with the same arguments each time `file` changes.
+///
+/// Each run is a new process so errors and crashes don't end the watch.
+fn watch_file(file: &str) -> Result<(), Box<dyn std::error::Error>> {
+	const POLL: Duration = Duration::from_millis(100);
+	// Editors may write a file in several steps.
+	const DEBOUNCE: Duration = Duration::from_millis(50);
+	let exe = std::env::current_exe()?;
+	let args = std::env::args().skip(1).filter(|a| a != "--watch");
+	let args = args.collect::<Vec<_>>();
+	let modified = || std::fs::metadata(file).and_then(|m| m.modified());
+	let (mut last, mut ran) = (None, false);
+	loop {
+		match modified() {
+			Ok(t) if Some(t) != last => {
+				std::thread::sleep(DEBOUNCE);
+				if modified().ok() != Some(t) {
+					continue;
 				}
-				dynasm!(jit ; mov [rbx + (a * 8).try_into().unwrap()], Rq(r.code()));
-				(a == 0).then(|| call_print(&mut jit, r));
-				last_reg = Some(r);
-			}
-			Op::SetA(a, b) => {
-				dynasm!(jit ; =>lbl);
-				let r = load_tape(a, &mut jit);
-				dynasm!(jit ; add Rq(r.code()), [rbx + (b * 8).try_into().unwrap()]);
-				dynasm!(jit ; mov [rbx + (a * 8).try_into().unwrap()], Rq(r.code()));
-				(a == 0).then(|| call_print(&mut jit, r));
-				last_reg = Some(r);
+				(last, ran) = (Some(t), true);
+				let start = Instant::now();
+				let status = Command::new(&exe).args(&args).status()?;
+				if !status.success() {
+					eprintln!("{}", status);
+				}
+				eprintln!("took {:?}", Instant::now() - start);
+				eprintln!("waiting for changes to {}", file);
 			}
-			Op::JmpV(a, b) => {
-				let a = load_tape(a, &mut jit);
-				dynasm!(jit
-					; =>lbl
-					; cmp Rq(a.code()), Rq(last_reg.unwrap().code())
-					; jne =>labels[i - b as usize - 2]
-				);
+			Ok(_) => (),
+			Err(e) if last.is_some() => {
+				eprintln!("{}: {}", file, e);
+				last = None;
 			}
-			Op::JmpA(_a, _b) => {
-				todo!()
+			Err(_) if ran => (),
+			Err(e) => Err(e)?,
+		}
+		std::thread::sleep(POLL);
+	}
+}
+
+const USAGE: &str = "usage: [--regs N] [--tape N] [--entry N] [--init-v N] [--warn-uninit] [--coverage] [--run-until CELL=VALUE] [--guard-pages] [--call-depth N] [--emit-shared FILE] [--emit-object FILE] [--bundle-runtime] [--compile-only] [-O0|-O1|-O2] [--pass NAME]... [--dump-ir-after NAME] [--dump-ir-all] [--analyze] [--alloc fifo|lru|freq] [--alloc-seed S] [--reg-log] [--block N] [--iterations N] [--asm-syntax intel|att] [--window N] [--batch] [--trace FILE] [--trace-format text|json|chrome] [--no-print] [--jump-mode relative|absolute] [--timeout SECS] [--pin-cpu N] [--perf] [--deterministic-timing] [--cell isize|i64|i32|u64|saturating] [--output char|byte|decimal] [--dump-image W H FILE] [--image-rgb] [--time-unit ns|us|ms|s] [--verify-deterministic N] [--why-slow] [--watch] [--strict] <interpreter|checked|jit|jit2|compare-alloc|bench-block|dump-both|handoff|ops|bench-parse> <file>\n       [--backend jit|jit2] codegen-diff <old> <new>\n       [--bundle-runtime] --emit-object FILE <jit|jit2> <file>...\n       [--seed N] [--stress [--no-calls]] <gen <ops>|fuzz-parse <iterations>|check-passes <iterations>>";
+
+/// Options that only apply to compiled code.
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+struct Compile {
+	emit_shared: Option<String>,
+	emit_object: Option<String>,
+	bundle_runtime: bool,
+	compile_only: bool,
+	block: usize,
+	iterations: u32,
+	syntax: disasm::Syntax,
+	verify_runs: Option<usize>,
+	why_slow: bool,
+	backend: String,
+	/// The file of the program, and the other programs given after it for `codegen-diff` and
+	/// `--emit-object` along with their files.
+	file: String,
+	more: Vec<(String, Vec<Op>, Config)>,
+}
+
+/// The symbol a program from `file` is exported as when several are compiled into one
+/// object: `run_` followed by the name of the file without its directory and extension, with
+/// characters other than ASCII letters, digits and `_` replaced by `_`.
+#[cfg(feature = "jit")]
+fn symbol_name(file: &str) -> String {
+	let stem = std::path::Path::new(file).file_stem().unwrap_or_default();
+	let stem = stem
+		.to_string_lossy()
+		.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+	format!("run_{}", stem)
+}
+
+/// Run the modes that need the JITs.
+#[cfg(feature = "jit")]
+fn compiled(
+	mode: &str,
+	f: Vec<Op>,
+	cfg: &Config,
+	compile: Compile,
+) -> Result<(), Box<dyn std::error::Error>> {
+	if let Some(out) = compile.emit_shared {
+		let f = match mode {
+			"jit" => jit(f, cfg, PrintCall::Bundled)?,
+			"jit2" => jit2(f, cfg, PrintCall::Bundled)?,
+			_ => Err("--emit-shared requires jit or jit2")?,
+		};
+		std::fs::write(out, elf::shared_object(f.code(), &[("run_program", 0)]))?;
+		return Ok(());
+	}
+	if let Some(out) = compile
+		.emit_object
+		.as_ref()
+		.filter(|_| !compile.more.is_empty())
+	{
+		let mut programs = Vec::new();
+		let mut names = Vec::<String>::new();
+		let first = (compile.file, f, cfg.clone());
+		for (file, ops, cfg) in std::iter::once(first).chain(compile.more) {
+			programs.push(match mode {
+				"jit" => jit(ops, &cfg, PrintCall::External)?,
+				"jit2" => jit2(ops, &cfg, PrintCall::External)?,
+				_ => Err("--emit-object requires jit or jit2")?,
+			});
+			let name = symbol_name(&file);
+			if names.contains(&name) {
+				Err(format!(
+					"more than one program would be exported as {}",
+					name
+				))?
 			}
+			names.push(name);
 		}
+		let linked = link(&programs, compile.bundle_runtime);
+		let names = names.iter().map(String::as_str);
+		let symbols = names.zip(linked.offsets).collect::<Vec<_>>();
+		let externals = linked.print_relocation.map(|r| ("print", r));
+		std::fs::write(
+			out,
+			elf::object(&linked.code, &symbols, externals.as_slice()),
+		)?;
+		return Ok(());
+	}
+	if let Some(out) = compile.emit_object {
+		let print = if compile.bundle_runtime {
+			PrintCall::Bundled
+		} else {
+			PrintCall::External
+		};
+		let f = match mode {
+			"jit" => jit(f, cfg, print)?,
+			"jit2" => jit2(f, cfg, print)?,
+			_ => Err("--emit-object requires jit or jit2")?,
+		};
+		let externals = f.print_relocation().map(|r| ("print", r));
+		let obj = elf::object(f.code(), &[("run_program", 0)], externals.as_slice());
+		std::fs::write(out, obj)?;
+		return Ok(());
 	}
-	dynasm!(jit
-		; pop r12
-		; pop r13
-		; pop r14
-		; pop r15
-		; pop rbp
-		; pop rbx
-		; ret
-	);
-	let f = jit.finalize().unwrap();
-	std::fs::write("/tmp/jit2.out", &f[..]).unwrap();
-	let f: extern "C" fn(*mut isize) =
-		unsafe { core::mem::transmute(f.ptr(dynasmrt::AssemblyOffset(0))) };
 	let t = Instant::now();
-	f([0; 0x10000].as_mut_ptr());
-	eprintln!("{:?}", Instant::now() - t);
+	let (f, dump) = match mode {
+		"bench-block" => {
+			return bench_block(f, cfg, compile.block, compile.iterations, compile.syntax);
+		}
+		"dump-both" => return dump_both(f, cfg, compile.syntax),
+		"handoff" => return handoff(f, cfg),
+		"codegen-diff" => {
+			let (new_file, new, new_cfg) = compile.more.into_iter().next().unwrap();
+			let programs = [(f, cfg), (new, &new_cfg)];
+			let files = [compile.file, new_file];
+			return codegen_diff(&compile.backend, files, programs, compile.syntax);
+		}
+		"compare-alloc" => return Ok(compare_alloc(f, cfg)?),
+		"jit" | "jit2" if compile.why_slow => {
+			let program = match mode {
+				"jit" => jit(f.clone(), cfg, PrintCall::Host)?,
+				_ => jit2(f.clone(), cfg, PrintCall::Host)?,
+			};
+			return why_slow(&f, cfg, &program, compile.syntax);
+		}
+		"jit" | "jit2" if compile.verify_runs.is_some() => {
+			let f = match mode {
+				"jit" => jit(f, cfg, PrintCall::Host)?,
+				_ => jit2(f, cfg, PrintCall::Host)?,
+			};
+			return verify_deterministic(&f, cfg, compile.verify_runs.unwrap());
+		}
+		"jit" => (jit(f, cfg, PrintCall::Host)?, "/tmp/jit.out"),
+		"jit2" => (jit2(f, cfg, PrintCall::Host)?, "/tmp/jit2.out"),
+		_ => Err(USAGE)?,
+	};
+	if compile.compile_only {
+		report_time(cfg, Instant::now() - t);
+		return Ok(());
+	}
+	if let Some(len) = f.required_tape_len().filter(|&l| l > cfg.tape_len) {
+		if !cfg.guard_pages {
+			Err(format!(
+				"the program needs a tape of {} cells, see --tape",
+				len
+			))?
+		}
+	}
+	std::fs::write(dump, f.code())?;
+	f.execute(cfg);
+	Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-	const USAGE: &str = "usage: <interpreter|jit|jit2> <file>";
-	let mut args = std::env::args().skip(1);
-	let mode = args.next().ok_or(USAGE)?;
-	let f = args.next().ok_or(USAGE)?;
-	let f = std::fs::read(f)?;
-	let f = parse(f);
-	match &*mode {
-		"interpreter" => run(f),
-		"jit" => jit(f),
-		"jit2" => jit2(f),
+#[cfg(not(feature = "jit"))]
+fn compiled(
+	mode: &str,
+	_: Vec<Op>,
+	_: &Config,
+	compile: Compile,
+) -> Result<(), Box<dyn std::error::Error>> {
+	if compile.emit_shared.is_some() || compile.emit_object.is_some() {
+		Err("--emit-shared and --emit-object require the jit feature")?
+	}
+	match mode {
+		"jit" | "jit2" | "bench-block" | "dump-both" | "handoff" | "compare-alloc"
+		| "codegen-diff" => Err(format!("{} requires the jit feature", mode))?,
 		_ => Err(USAGE)?,
 	}
-	Ok(())
+}
+
+fn main() -> ExitCode {
+	match cli() {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("error: {}", e);
+			ExitCode::FAILURE
+		}
+	}
+}
+
+/// Parse the arguments and run the mode they select.
+fn cli() -> Result<(), Box<dyn std::error::Error>> {
+	let mut regs = JIT2_REGS;
+	let (mut emit_shared, mut emit_object, mut bundle_runtime) = (None, None, false);
+	let (mut compile_only, mut seed, mut stress) = (false, 0, false);
+	let mut no_calls = false;
+	let mut passes = opt::PassManager::default();
+	let (mut dump_ir_after, mut dump_ir_all) = (None, false);
+	let (mut analyze, mut callgraph, mut guard_pages) = (false, false, false);
+	let mut call_depth = CALL_DEPTH;
+	let (mut alloc, mut reg_log, mut alloc_seed) = (Alloc::Fifo, false, None);
+	let (mut block, mut iterations) = (0, 1_000_000);
+	let mut syntax = disasm::Syntax::Intel;
+	let (mut window, mut watch, mut strict) = (None, false, false);
+	let (mut batch, mut print) = (false, true);
+	let (mut jump_mode, mut timeout) = (JumpMode::Relative, None);
+	let (mut pin_cpu, mut perf, mut cycles) = (None, false, false);
+	let (mut trace, mut trace_format) = (None, trace::Format::Text);
+	let (mut output, mut time_unit) = (Output::Char, None);
+	let (mut image, mut image_rgb) = (None, false);
+	let (mut verify_runs, mut why_slow) = (None, false);
+	let mut interpret: fn(Vec<Op>, &Config) = run;
+	let mut saturating = false;
+	let (mut warn_uninit, mut coverage, mut run_until) = (false, false, None);
+	let (mut tape, mut entry, mut init_v) = (None, None, 0);
+	let (mut mode, mut file, mut more_files) = (None, None, Vec::new());
+	let mut backend = "jit2".to_string();
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match &*arg {
+			"--regs" => {
+				regs = args.next().ok_or(USAGE)?.parse()?;
+				if !(1..=JIT2_REGS).contains(&regs) {
+					Err("--regs must be between 1 and 5")?
+				}
+			}
+			"--tape" => tape = Some(args.next().ok_or(USAGE)?.parse()?),
+			"--entry" => entry = Some(args.next().ok_or(USAGE)?.parse()?),
+			"--init-v" => init_v = args.next().ok_or(USAGE)?.parse()?,
+			"--warn-uninit" => warn_uninit = true,
+			"--coverage" => coverage = true,
+			"--run-until" => {
+				let arg = args.next().ok_or(USAGE)?;
+				let (cell, value) = arg.split_once('=').ok_or(USAGE)?;
+				run_until = Some((cell.parse()?, value.parse()?));
+			}
+			"--emit-shared" => emit_shared = Some(args.next().ok_or(USAGE)?),
+			"--emit-object" => emit_object = Some(args.next().ok_or(USAGE)?),
+			"--bundle-runtime" => bundle_runtime = true,
+			"--compile-only" => compile_only = true,
+			"--seed" => seed = args.next().ok_or(USAGE)?.parse()?,
+			"--stress" => stress = true,
+			"--no-calls" => no_calls = true,
+			"--pass" => {
+				let name = args.next().ok_or(USAGE)?;
+				let pass = opt::PASSES.iter().find(|(n, _)| *n == name);
+				passes.passes.push(*pass.ok_or("unknown pass")?);
+			}
+			"-O0" | "-O1" | "-O2" => {
+				passes = opt::PassManager::level(arg[2..].parse()?).unwrap();
+			}
+			"--dump-ir-after" => dump_ir_after = Some(args.next().ok_or(USAGE)?),
+			"--dump-ir-all" => dump_ir_all = true,
+			"--analyze" => analyze = true,
+			"--callgraph" => callgraph = true,
+			"--guard-pages" => guard_pages = true,
+			"--call-depth" => call_depth = args.next().ok_or(USAGE)?.parse()?,
+			"--alloc" => alloc = args.next().ok_or(USAGE)?.parse()?,
+			"--reg-log" => reg_log = true,
+			"--alloc-seed" => alloc_seed = Some(args.next().ok_or(USAGE)?.parse()?),
+			"--block" => block = args.next().ok_or(USAGE)?.parse()?,
+			"--iterations" => {
+				iterations = args.next().ok_or(USAGE)?.parse()?;
+				if iterations == 0 {
+					Err("--iterations must be at least 1")?
+				}
+			}
+			"--asm-syntax" => syntax = args.next().ok_or(USAGE)?.parse()?,
+			"--backend" => backend = args.next().ok_or(USAGE)?,
+			"--output" => output = args.next().ok_or(USAGE)?.parse()?,
+			"--watch" => watch = true,
+			"--strict" => strict = true,
+			"--batch" => batch = true,
+			"--no-print" => print = false,
+			"--jump-mode" => jump_mode = args.next().ok_or(USAGE)?.parse()?,
+			"--timeout" => {
+				let secs = args.next().ok_or(USAGE)?.parse()?;
+				timeout = Some(Duration::try_from_secs_f64(secs)?);
+			}
+			"--pin-cpu" => pin_cpu = Some(args.next().ok_or(USAGE)?.parse()?),
+			"--perf" => perf = true,
+			"--deterministic-timing" => cycles = true,
+			"--trace" => trace = Some(args.next().ok_or(USAGE)?.into()),
+			"--trace-format" => trace_format = args.next().ok_or(USAGE)?.parse()?,
+			"--dump-image" => {
+				let width = args.next().ok_or(USAGE)?.parse()?;
+				let height = args.next().ok_or(USAGE)?.parse()?;
+				image = Some((width, height, args.next().ok_or(USAGE)?));
+			}
+			"--image-rgb" => image_rgb = true,
+			"--why-slow" => why_slow = true,
+			"--verify-deterministic" => verify_runs = Some(args.next().ok_or(USAGE)?.parse()?),
+			"--time-unit" => time_unit = Some(args.next().ok_or(USAGE)?.parse::<TimeUnit>()?),
+			"--cell" => {
+				let cell = args.next().ok_or(USAGE)?;
+				saturating = cell == "saturating";
+				interpret = match &*cell {
+					"isize" => run,
+					"i64" => run_with::<i64>,
+					"i32" => run_with::<i32>,
+					"u64" => run_with::<u64>,
+					"saturating" => run_with::<Saturating<i64>>,
+					_ => Err("--cell must be isize, i64, i32, u64 or saturating")?,
+				}
+			}
+			"--window" => {
+				window = Some(args.next().ok_or(USAGE)?.parse()?);
+				if window == Some(0) {
+					Err("--window must be at least 1")?
+				}
+			}
+			_ if mode.is_none() => mode = Some(arg),
+			_ if file.is_none() => file = Some(arg),
+			_ => more_files.push(arg),
+		}
+	}
+	let mode = mode.ok_or(USAGE)?;
+	let file = file.ok_or(USAGE)?;
+	let more_ok = match &*mode {
+		"codegen-diff" => more_files.len() == 1,
+		_ => more_files.is_empty() || emit_object.is_some(),
+	};
+	if !more_ok {
+		Err(USAGE)?
+	}
+	// The passes fold additions as if cells wrap around.
+	if saturating && !passes.passes.is_empty() {
+		Err("--cell saturating can't be combined with -O1, -O2 or --pass")?
+	}
+	if watch {
+		return watch_file(&file);
+	}
+	if mode == "gen" {
+		let ops = if stress {
+			println!("#!tape {}", gen::STRESS_TAPE);
+			match no_calls {
+				true => gen::stress_without_calls(file.parse()?, seed),
+				false => gen::stress(file.parse()?, seed),
+			}
+		} else if no_calls {
+			Err("--no-calls only applies to --stress")?
+		} else {
+			gen::program(file.parse()?, seed)
+		};
+		ops.iter().for_each(|op| println!("{}", op));
+		return Ok(());
+	}
+	if mode == "fuzz-parse" {
+		return Ok(fuzz_parse(file.parse()?, seed)?);
+	}
+	if mode == "check-passes" {
+		return check_passes(file.parse()?, seed);
+	}
+	if perf && !cfg!(all(feature = "perf", target_os = "linux")) {
+		Err("--perf requires the perf feature and Linux")?
+	}
+	if let Some(cpu) = pin_cpu {
+		perf::pin_cpu(cpu).map_err(|e| format!("--pin-cpu: {}", e))?;
+	}
+	let f = std::fs::read(&file)?;
+	if mode == "bench-parse" {
+		bench_parse(f);
+		return Ok(());
+	}
+	let (f, spans, options) = parse_with_spans(f, strict).map_err(|e| e.to_string())?;
+	let f = jump_mode.to_relative(f);
+	let entry_arg = entry;
+	let mut entry = entry.or(options.entry).unwrap_or(0);
+	let f = passes.run(f, &mut entry, |name, ops| {
+		if dump_ir_all || dump_ir_after.as_deref() == Some(name) {
+			eprintln!("# after {}", name);
+			ops.iter().for_each(|op| eprintln!("{}", op));
+		}
+	});
+	if mode == "ops" {
+		print_ops(&f);
+		return Ok(());
+	}
+	let cfg = Config {
+		tape_len: tape.or(options.tape).unwrap_or(TAPE_LEN),
+		tape_data: options.initial_tape(),
+		entry,
+		init_v,
+		regs,
+		warn_uninit,
+		coverage,
+		run_until,
+		guard_pages,
+		call_depth,
+		alloc,
+		alloc_seed,
+		pins: options.pins,
+		reg_log,
+		window,
+		batch,
+		trace,
+		trace_format,
+		print,
+		jump_mode,
+		timeout,
+		perf,
+		cycles,
+		// Passes may remove or move ops.
+		spans: passes.passes.is_empty().then_some(spans),
+		output,
+		time_unit,
+	};
+	if cfg.tape_data.len() > cfg.tape_len {
+		Err(format!(
+			"the data needs a tape of {} cells, see --tape",
+			cfg.tape_data.len()
+		))?
+	}
+	if why_slow && mode != "jit" && mode != "jit2" {
+		Err("--why-slow requires jit or jit2")?
+	}
+	if cycles && mode != "jit" && mode != "jit2" {
+		Err("--deterministic-timing requires jit or jit2")?
+	}
+	// Other programs are parsed and optimized the same way, with their own directives.
+	let load = |file: String| -> Result<_, Box<dyn std::error::Error>> {
+		let source = std::fs::read(&file)?;
+		let (ops, _, options) = parse_with_spans(source, strict).map_err(|e| e.to_string())?;
+		let ops = jump_mode.to_relative(ops);
+		let mut entry = entry_arg.or(options.entry).unwrap_or(0);
+		let ops = passes.run(ops, &mut entry, |_, _| ());
+		let cfg = Config {
+			tape_len: tape.or(options.tape).unwrap_or(TAPE_LEN),
+			tape_data: options.initial_tape(),
+			entry,
+			pins: options.pins,
+			spans: None,
+			..cfg.clone()
+		};
+		Ok((file, ops, cfg))
+	};
+	let more = more_files.into_iter().map(load).collect::<Result<_, _>>()?;
+	let compile = Compile {
+		emit_shared,
+		emit_object,
+		bundle_runtime,
+		compile_only,
+		block,
+		iterations,
+		syntax,
+		verify_runs,
+		why_slow,
+		backend,
+		file,
+		more,
+	};
+	if analyze {
+		analyze::report(&f, entry);
+		#[cfg(feature = "jit")]
+		{
+			let programs = [
+				("jit", jit(f.clone(), &cfg, PrintCall::Host)),
+				("jit2", jit2(f.clone(), &cfg, PrintCall::Host)),
+			];
+			analyze::report_code(&f, &programs);
+		}
+		return Ok(());
+	}
+	if callgraph {
+		print!("{}", analyze::callgraph(&f, entry));
+		return Ok(());
+	}
+	if compile.emit_shared.is_none() && compile.emit_object.is_none() {
+		match &*mode {
+			"interpreter" => {
+				interpret(f, &cfg);
+				return Ok(());
+			}
+			"checked" => {
+				let tape = run_checked(f, &cfg)?;
+				if let Some((width, height, out)) = image {
+					dump_image(&tape, width, height, image_rgb, &out)?;
+				}
+				return Ok(());
+			}
+			_ => (),
+		}
+	}
+	if image.is_some() {
+		Err("--dump-image requires checked")?
+	}
+	compiled(&mode, f, &cfg, compile)
 }
@@ -0,0 +1,269 @@
+//! Optimization passes, which transform ops without changing what a program does.
+//!
+//! Passes take the ops and the index of the first op to execute, which is updated if ops
+//! are removed or moved.
+
+use crate::{capture, Checked, Config, Op};
+
+pub type Pass = fn(Vec<Op>, &mut usize) -> Vec<Op>;
+
+/// All passes by name.
+pub const PASSES: &[(&str, Pass)] = &[("fold", fold)];
+
+/// Find a pass by name.
+pub fn pass(name: &str) -> Option<Pass> {
+	PASSES.iter().find(|(n, _)| *n == name).map(|(_, p)| *p)
+}
+
+/// Runs passes in order, optionally again until they don't change the ops anymore.
+///
+/// ```
+/// use dynasm_experiments::{opt::PassManager, parse, Op};
+///
+/// let (ops, _) = parse(b"1`+1 1`+2 1`+3 0`+65 0`+1 2`+1 2`+-1".to_vec());
+/// let mut entry = 0;
+/// let mut dumps = Vec::new();
+/// let ops = PassManager::level(2)
+///     .unwrap()
+///     .run(ops, &mut entry, |name, ops| dumps.push((name, ops.to_vec())));
+/// let folded = parse(b"1`+6 0`+65 0`+1 2`+0".to_vec()).0;
+/// assert_eq!(folded, [Op::SetV(1, 6), Op::SetV(0, 65), Op::SetV(0, 1), Op::SetV(2, 0)]);
+/// assert_eq!(ops, folded);
+/// // Each pass is shown the ops it left, as --dump-ir-after prints them. The second run of
+/// // fold changes nothing.
+/// assert_eq!(dumps, [("fold", folded.clone()), ("fold", folded)]);
+/// ```
+#[derive(Clone, Default)]
+pub struct PassManager {
+	/// The passes to run and their names.
+	pub passes: Vec<(&'static str, Pass)>,
+	/// Whether to run all passes again until the ops and entry stay the same, at most
+	/// [`MAX_ITERATIONS`](Self::MAX_ITERATIONS) times.
+	pub fixpoint: bool,
+}
+
+impl PassManager {
+	/// The most times all passes run when iterating to a fixpoint, in case passes keep
+	/// undoing each other.
+	pub const MAX_ITERATIONS: usize = 16;
+
+	/// The passes of an optimization level. Level 0 runs no passes, level 1 runs each pass
+	/// once and level 2 iterates them to a fixpoint.
+	pub fn level(level: u8) -> Option<Self> {
+		let passes = PASSES.to_vec();
+		match level {
+			0 => Some(Self::default()),
+			1 => Some(Self {
+				passes,
+				fixpoint: false,
+			}),
+			2 => Some(Self {
+				passes,
+				fixpoint: true,
+			}),
+			_ => None,
+		}
+	}
+
+	/// Run the passes. `after` is called with the name of each pass and the ops after it
+	/// ran.
+	pub fn run(
+		&self,
+		mut ops: Vec<Op>,
+		entry: &mut usize,
+		mut after: impl FnMut(&'static str, &[Op]),
+	) -> Vec<Op> {
+		let runs = if self.fixpoint {
+			Self::MAX_ITERATIONS
+		} else {
+			1
+		};
+		for _ in 0..runs {
+			let (before, entry_before) = (ops.clone(), *entry);
+			for &(name, pass) in &self.passes {
+				ops = pass(ops, entry);
+				after(name, &ops);
+			}
+			if ops == before && *entry == entry_before {
+				break;
+			}
+		}
+		ops
+	}
+}
+
+/// Indices of ops which execution may continue at other than by falling through, or `None`
+/// if they can't be determined.
+fn jump_targets(ops: &[Op], entry: usize) -> Option<Vec<bool>> {
+	let mut targets = vec![false; ops.len() + 1];
+	if let Some(t) = targets.get_mut(entry) {
+		*t = true;
+	}
+	for (i, op) in ops.iter().enumerate() {
+		match *op {
+			Op::JmpV(_, b) => {
+				if let Some(t) = i.checked_add_signed(b).and_then(|t| targets.get_mut(t)) {
+					*t = true;
+				}
+			}
+			Op::Call(b) => {
+				if let Some(t) = i.checked_add_signed(b).and_then(|t| targets.get_mut(t)) {
+					*t = true;
+				}
+				// Where the call returns to.
+				targets[i + 1] = true;
+			}
+			Op::JmpA(..) => return None,
+			Op::SetV(..) | Op::SetA(..) | Op::Ret | Op::ReadInts(_) | Op::Swap(..) => (),
+		}
+	}
+	Some(targets)
+}
+
+/// Remove all ops for which `keep` is `false` and adjust jumps and `entry` accordingly.
+///
+/// Removed ops may not be jump targets.
+fn remove(ops: &[Op], keep: &[bool], entry: &mut usize) -> Vec<Op> {
+	// The new index of each op and of the end of the program.
+	let mut new = Vec::with_capacity(ops.len() + 1);
+	new.push(0);
+	for &k in keep {
+		new.push(new.last().unwrap() + usize::from(k));
+	}
+	// Targets outside the program keep the same distance to it.
+	let map = |t: isize| match usize::try_from(t) {
+		Ok(t) if t < new.len() => new[t] as isize,
+		Ok(_) => t - ops.len() as isize + new[ops.len()] as isize,
+		Err(_) => t,
+	};
+	*entry = map(*entry as isize) as usize;
+	let ops = ops.iter().enumerate().filter(|(i, _)| keep[*i]);
+	ops.map(|(i, op)| match *op {
+		Op::JmpV(a, b) => {
+			let t = (i as isize).wrapping_add(b);
+			Op::JmpV(a, map(t).wrapping_sub(new[i] as isize))
+		}
+		Op::Call(b) => {
+			let t = (i as isize).wrapping_add(b);
+			Op::Call(map(t).wrapping_sub(new[i] as isize))
+		}
+		op => op,
+	})
+	.collect()
+}
+
+/// Merge consecutive additions of constants to the same cell.
+///
+/// Additions to cell 0 are kept since each one prints.
+pub fn fold(ops: Vec<Op>, entry: &mut usize) -> Vec<Op> {
+	let Some(targets) = jump_targets(&ops, *entry) else {
+		return ops;
+	};
+	let mut keep = vec![true; ops.len()];
+	let mut ops = ops;
+	let mut last = None;
+	for i in 0..ops.len() {
+		if let (Some(l), Op::SetV(a, y)) = (last, ops[i]) {
+			if let Op::SetV(p, x) = ops[l] {
+				if p == a && a != 0 && !targets[i] {
+					ops[l] = Op::SetV(a, x.wrapping_add(y));
+					keep[i] = false;
+					continue;
+				}
+			}
+		}
+		last = Some(i);
+	}
+	remove(&ops, &keep, entry)
+}
+
+/// What a program did in the checked interpreter, which passes must not change.
+struct Outcome {
+	output: Vec<u8>,
+	tape: Vec<isize>,
+	v: isize,
+	failed: bool,
+}
+
+/// Run ops in the checked interpreter, or `None` if they don't finish within `max_steps`.
+fn outcome(ops: &[Op], cfg: &Config, max_steps: u64) -> Option<Outcome> {
+	let mut vm = Checked::new(ops, cfg);
+	let (failed, output) = capture(|| {
+		for _ in 0..max_steps {
+			match vm.step() {
+				Ok(true) => (),
+				Ok(false) => return Some(false),
+				Err(_) => return Some(true),
+			}
+		}
+		None
+	});
+	let state = vm.snapshot();
+	Some(Outcome {
+		output,
+		tape: state.tape().to_vec(),
+		v: state.v(),
+		failed: failed?,
+	})
+}
+
+/// Run a program in the checked interpreter before and after a pass and describe how what it
+/// does differs, if it does. A program starts at `cfg.entry` before the pass.
+///
+/// Programs that don't finish within `max_steps` before the pass can't be compared and give
+/// `None`. After the pass they get as many steps, so a pass may not make a program slower
+/// than that. Programs that fail must fail after the pass as well, after printing the same
+/// and leaving the same tape.
+///
+/// ```
+/// use dynasm_experiments::{opt::{check_pass, fold}, parse, Config, Op};
+///
+/// let (ops, _) = parse(b"1`+2 1`+3 0`1 0`+-5".to_vec());
+/// let cfg = Config { tape_len: 2, ..Config::default() };
+/// assert_eq!(check_pass(fold, &ops, &cfg, 100), None);
+///
+/// // Merging the additions to cell 0 would leave out a print.
+/// fn fold_all(ops: Vec<Op>, _: &mut usize) -> Vec<Op> {
+///     vec![Op::SetV(1, 5), Op::SetV(0, 0)]
+/// }
+/// let e = check_pass(fold_all, &ops, &cfg, 100).unwrap();
+/// assert_eq!(e, r#"printed "\0" instead of "\u{5}\0""#);
+/// ```
+pub fn check_pass(pass: Pass, ops: &[Op], cfg: &Config, max_steps: u64) -> Option<String> {
+	let before = outcome(ops, cfg, max_steps)?;
+	let mut entry = cfg.entry;
+	let optimized = pass(ops.to_vec(), &mut entry);
+	let cfg = Config {
+		entry,
+		// The spans are those of the ops before the pass.
+		spans: None,
+		..cfg.clone()
+	};
+	let Some(after) = outcome(&optimized, &cfg, max_steps) else {
+		return Some(format!("didn't finish within {} steps", max_steps));
+	};
+	if after.output != before.output {
+		let [a, b] = [&after.output, &before.output].map(|o| String::from_utf8_lossy(o));
+		return Some(format!("printed {:?} instead of {:?}", a, b));
+	}
+	if after.failed != before.failed {
+		let failed = |f| if f { "failed" } else { "finished" };
+		let e = format!(
+			"{} instead of {}",
+			failed(after.failed),
+			failed(before.failed)
+		);
+		return Some(e);
+	}
+	if let Some(i) = (0..before.tape.len()).find(|&i| after.tape[i] != before.tape[i]) {
+		let (a, b) = (after.tape[i], before.tape[i]);
+		return Some(format!("left {} in cell {} instead of {}", a, i, b));
+	}
+	if after.v != before.v {
+		return Some(format!(
+			"ended with V = {} instead of {}",
+			after.v, before.v
+		));
+	}
+	None
+}
@@ -0,0 +1,292 @@
+//! Parser for the textual form of programs.
+
+use crate::Op;
+use std::fmt;
+
+/// Parse a decimal integer, which fails if it does not fit in an `isize`.
+fn parse_int(code: &mut Vec<u8>) -> Result<isize, ()> {
+	let c = code.pop();
+	let inv = if c == Some(b'-') {
+		true
+	} else {
+		code.extend(c);
+		false
+	};
+	// Accumulate negatively, since isize::MIN has no positive counterpart.
+	let mut n = Some(0isize);
+	while let Some(c) = code.pop() {
+		if !c.is_ascii_digit() {
+			code.push(c);
+			break;
+		}
+		n = n.and_then(|n| n.checked_mul(10)?.checked_sub((c - b'0') as isize));
+	}
+	if inv {
+		n
+	} else {
+		n.and_then(isize::checked_neg)
+	}
+	.ok_or(())
+}
+
+/// Ends the ops of a program outside of comments. The bytes after the end of its line are
+/// data for the tape.
+pub const DATA_MARKER: &[u8] = b"%%";
+
+/// Options a program sets for itself with directives and its data.
+#[derive(Debug, Default)]
+pub struct ProgramOptions {
+	pub tape: Option<usize>,
+	pub entry: Option<usize>,
+	/// Cells to keep in registers, in the order they were pinned.
+	pub pins: Vec<isize>,
+	/// The bytes after the line with the [`DATA_MARKER`].
+	pub data: Vec<u8>,
+}
+
+impl ProgramOptions {
+	/// The cells the data sets before the program starts, from cell 0 on. Byte N of the data
+	/// is cell N + 1, which leaves cell 0 for output. There are no cells without data.
+	///
+	/// ```
+	/// use dynasm_experiments::parse;
+	///
+	/// let (ops, options) = parse(b"1`+1\n%%\nHi\n1`+1".to_vec());
+	/// assert_eq!(ops.len(), 1);
+	/// assert_eq!(options.data, b"Hi\n1`+1");
+	/// assert_eq!(options.initial_tape(), [0, 72, 105, 10, 49, 96, 43, 49]);
+	///
+	/// assert!(parse(b"1`+1".to_vec()).1.initial_tape().is_empty());
+	/// ```
+	pub fn initial_tape(&self) -> Vec<isize> {
+		if self.data.is_empty() {
+			return Vec::new();
+		}
+		let data = self.data.iter().map(|&b| isize::from(b));
+		std::iter::once(0).chain(data).collect()
+	}
+}
+
+fn parse_directive(line: &[u8], options: &mut ProgramOptions) -> Option<()> {
+	let line = std::str::from_utf8(line).ok()?.strip_prefix('!')?;
+	let (name, value) = line.split_once(char::is_whitespace)?;
+	let value = value.trim();
+	match name {
+		"tape" => options.tape = Some(value.parse().ok()?),
+		"entry" => options.entry = Some(value.parse().ok()?),
+		"pin" => options.pins.push(value.parse().ok()?),
+		_ => (),
+	}
+	Some(())
+}
+
+/// A syntax error found by [`parse_strict`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+	/// An op is missing a part, such as the `` ` `` or a number, or a number does not fit in
+	/// an `isize`.
+	IncompleteOp { offset: usize },
+	/// A byte that does not start an op, comment or whitespace.
+	UnexpectedByte { offset: usize, byte: u8 },
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::IncompleteOp { offset } => write!(f, "incomplete op at byte {}", offset),
+			Self::UnexpectedByte { offset, byte } => {
+				write!(f, "unexpected {:?} at byte {}", *byte as char, offset)
+			}
+		}
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse the ops of a program and the options it sets with directives.
+///
+/// Parsing stops at the [`DATA_MARKER`], and the bytes after the end of its line are the
+/// program's data. Anything else that is not an op is ignored. Rendering ops with [`Display`](std::fmt::Display)
+/// gives source that parses to the same ops:
+///
+/// ```
+/// use dynasm_experiments::{parse, Op};
+///
+/// let ops = [
+///     Op::SetV(-1, 0),
+///     Op::SetA(isize::MIN, isize::MAX),
+///     Op::JmpV(isize::MAX, isize::MIN),
+///     Op::JmpA(0, -1),
+///     Op::ReadInts(-5),
+///     Op::Swap(3, -3),
+/// ];
+/// let code = ops.map(|op| op.to_string()).join(" ");
+/// assert_eq!(parse(code.into_bytes()).0, ops);
+///
+/// // A stray backtick is skipped.
+/// assert_eq!(parse(b"1`+1 ` 2`+1".to_vec()).0, [Op::SetV(1, 1), Op::SetV(2, 1)]);
+/// ```
+///
+/// Directives only count before the first op, and unknown ones are comments:
+///
+/// ```
+/// use dynasm_experiments::{parse, Op};
+///
+/// let code = b"#!tape 3\n#!entry 1\n#!pin 2\n#!pin -1\n#!frob 4\n1`+1 #!tape 9\n2`1".to_vec();
+/// let (ops, options) = parse(code);
+/// assert_eq!(ops, [Op::SetV(1, 1), Op::SetA(2, 1)]);
+/// assert_eq!((options.tape, options.entry), (Some(3), Some(1)));
+/// assert_eq!(options.pins, [2, -1]);
+///
+/// // A directive without a valid value is ignored.
+/// let (_, options) = parse(b"#!tape many\n#!entry -1\n1`+1".to_vec());
+/// assert_eq!((options.tape, options.entry), (None, None));
+/// ```
+pub fn parse(code: Vec<u8>) -> (Vec<Op>, ProgramOptions) {
+	let (ops, _, options) = parse_code(code, false).expect("only strict parsing fails");
+	(ops, options)
+}
+
+/// Parse like [`parse`], but fail on anything outside comments that is neither an op nor
+/// whitespace.
+///
+/// ```
+/// use dynasm_experiments::{parse_strict, Op, ParseError};
+///
+/// let (ops, _) = parse_strict(b"1`+1 # comments may contain ` anything\n 2`+1\n".to_vec()).unwrap();
+/// assert_eq!(ops, [Op::SetV(1, 1), Op::SetV(2, 1)]);
+///
+/// let e = parse_strict(b"1`+1 ` 2`+1".to_vec()).unwrap_err();
+/// assert_eq!(e, ParseError::UnexpectedByte { offset: 5, byte: b'`' });
+///
+/// // Operators need all their numbers.
+/// let e = parse_strict(b"1`+1 2`+".to_vec()).unwrap_err();
+/// assert_eq!(e, ParseError::IncompleteOp { offset: 5 });
+/// ```
+pub fn parse_strict(code: Vec<u8>) -> Result<(Vec<Op>, ProgramOptions), ParseError> {
+	parse_code(code, true).map(|(ops, _, options)| (ops, options))
+}
+
+/// Parse like [`parse`] or [`parse_strict`], and also return the byte offset in the source
+/// at which each op starts.
+///
+/// ```
+/// use dynasm_experiments::parse_with_spans;
+///
+/// let (_, spans, _) = parse_with_spans(b"# count\n1`+1\n  >-1 <".to_vec(), true).unwrap();
+/// assert_eq!(spans, [8, 15, 19]);
+/// ```
+pub fn parse_with_spans(code: Vec<u8>, strict: bool) -> Result<Parsed, ParseError> {
+	parse_code(code, strict)
+}
+
+/// Ops, the byte offset each starts at and the options the program sets.
+pub type Parsed = (Vec<Op>, Vec<usize>, ProgramOptions);
+
+/// Whether a decimal integer follows. `code` is reversed.
+fn int_follows(code: &[u8]) -> bool {
+	match code {
+		[.., d, b'-'] | [.., d] => d.is_ascii_digit(),
+		[] => false,
+	}
+}
+
+/// Parse ops along with the byte offset in the source each starts at.
+fn parse_code(mut code: Vec<u8>, strict: bool) -> Result<Parsed, ParseError> {
+	let mut ops = Vec::new();
+	let mut options = ProgramOptions::default();
+	let len = code.len();
+	code.reverse();
+	while let Some(b) = code.pop() {
+		let offset = len - code.len() - 1;
+		// In strict mode a malformed op is an error, otherwise it is skipped.
+		let incomplete = || {
+			if strict {
+				Err(ParseError::IncompleteOp { offset })
+			} else {
+				Ok(())
+			}
+		};
+		let int = |code: &mut Vec<u8>| {
+			if strict && !int_follows(code) {
+				return Err(());
+			}
+			parse_int(code)
+		};
+		match b {
+			b'#' => {
+				let mut line = Vec::new();
+				while let Some(c) = code.pop().filter(|&c| c != b'\n') {
+					line.push(c);
+				}
+				if ops.is_empty() {
+					parse_directive(&line, &mut options);
+				}
+			}
+			b'>' => match int(&mut code) {
+				Ok(b) => ops.push((Op::Call(b), offset)),
+				Err(()) => incomplete()?,
+			},
+			b'<' => ops.push((Op::Ret, offset)),
+			b'%' if code.last() == Some(&DATA_MARKER[1]) => {
+				while code.pop().is_some_and(|c| c != b'\n') {}
+				options.data = std::mem::take(&mut code);
+				options.data.reverse();
+				break;
+			}
+			b',' => match int(&mut code) {
+				Ok(a) => ops.push((Op::ReadInts(a), offset)),
+				Err(()) => incomplete()?,
+			},
+			b'~' => {
+				let Ok(a) = int(&mut code) else {
+					incomplete()?;
+					continue;
+				};
+				if code.pop() != Some(b'`') {
+					incomplete()?;
+					continue;
+				}
+				let Ok(b) = int(&mut code) else {
+					incomplete()?;
+					continue;
+				};
+				ops.push((Op::Swap(a, b), offset));
+			}
+			_ if b == b'+' || b == b'-' || b.is_ascii_digit() => {
+				let jump = b == b'+';
+				if !jump {
+					code.push(b);
+				}
+				let Ok(a) = int(&mut code) else {
+					incomplete()?;
+					continue;
+				};
+				if code.pop() != Some(b'`') {
+					incomplete()?;
+					continue;
+				}
+				let chr = code.pop();
+				let addr = chr != Some(b'+');
+				if addr {
+					code.extend(chr);
+				}
+				let Ok(b) = int(&mut code) else {
+					incomplete()?;
+					continue;
+				};
+				let op = match (jump, addr) {
+					(false, false) => Op::SetV(a, b),
+					(false, true) => Op::SetA(a, b),
+					(true, false) => Op::JmpV(a, b),
+					(true, true) => Op::JmpA(a, b),
+				};
+				ops.push((op, offset));
+			}
+			_ if !strict || b.is_ascii_whitespace() => (),
+			byte => return Err(ParseError::UnexpectedByte { offset, byte }),
+		}
+	}
+	let (ops, spans) = ops.into_iter().unzip();
+	Ok((ops, spans, options))
+}
@@ -0,0 +1,189 @@
+//! Measurements beyond the wall clock time of a run.
+//!
+//! Hardware counters need the `perf` feature and Linux, where they are read with
+//! `perf_event_open`.
+
+use crate::Config;
+use std::fmt;
+use std::io;
+
+/// Restrict the current thread, and threads it starts afterwards, to one CPU.
+pub fn pin_cpu(cpu: usize) -> io::Result<()> {
+	#[cfg(target_os = "linux")]
+	{
+		if cpu >= libc::CPU_SETSIZE as usize {
+			return Err(io::Error::from_raw_os_error(libc::EINVAL));
+		}
+		// SAFETY: the set is plain data and the CPU is within it.
+		unsafe {
+			let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+			libc::CPU_SET(cpu, &mut set);
+			if libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) != 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		Ok(())
+	}
+	#[cfg(not(target_os = "linux"))]
+	{
+		let _ = cpu;
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"pinning to a CPU requires Linux",
+		))
+	}
+}
+
+/// Counts of hardware events during a run.
+///
+/// ```
+/// use dynasm_experiments::perf::Counts;
+///
+/// let counts = Counts { cache_misses: 12, branch_misses: 3 };
+/// assert_eq!(counts.to_string(), "12 cache misses, 3 branch mispredictions");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+	pub cache_misses: u64,
+	pub branch_misses: u64,
+}
+
+impl fmt::Display for Counts {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{} cache misses, {} branch mispredictions",
+			self.cache_misses, self.branch_misses
+		)
+	}
+}
+
+#[cfg(all(feature = "perf", target_os = "linux"))]
+mod counters {
+	use super::Counts;
+	use std::fs::File;
+	use std::io::{self, Read};
+	use std::os::unix::io::{AsRawFd, FromRawFd};
+
+	const PERF_TYPE_HARDWARE: u32 = 0;
+	const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+	const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+	const PERF_FORMAT_GROUP: u64 = 1 << 3;
+	const PERF_EVENT_IOC_ENABLE: u64 = 0x2400;
+	const PERF_EVENT_IOC_DISABLE: u64 = 0x2401;
+	const PERF_IOC_FLAG_GROUP: u64 = 1;
+
+	/// The first version of `struct perf_event_attr`, which is all that's needed here.
+	#[repr(C)]
+	#[derive(Default)]
+	struct Attr {
+		kind: u32,
+		size: u32,
+		config: u64,
+		sample_period: u64,
+		sample_type: u64,
+		read_format: u64,
+		/// Bit 0 is `disabled`, bit 5 `exclude_kernel` and bit 6 `exclude_hv`.
+		flags: u64,
+		wakeup_events: u32,
+		bp_type: u32,
+		config1: u64,
+	}
+
+	/// A group of counters for the current thread, which count while it runs on any CPU.
+	pub struct Counters {
+		leader: File,
+		_branch_misses: File,
+	}
+
+	fn open(config: u64, group: i32) -> io::Result<File> {
+		let attr = Attr {
+			kind: PERF_TYPE_HARDWARE,
+			size: std::mem::size_of::<Attr>() as u32,
+			config,
+			read_format: PERF_FORMAT_GROUP,
+			// Only the leader starts disabled, which keeps the whole group disabled.
+			flags: u64::from(group == -1) | 1 << 5 | 1 << 6,
+			..Attr::default()
+		};
+		// SAFETY: the attributes are valid for the duration of the call.
+		let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, &attr, 0, -1, group, 0) };
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		// SAFETY: the kernel returned a new file descriptor.
+		Ok(unsafe { File::from_raw_fd(fd as i32) })
+	}
+
+	fn ioctl(f: &File, request: u64) -> io::Result<()> {
+		// SAFETY: the file is a perf event and the request takes no pointer.
+		match unsafe { libc::ioctl(f.as_raw_fd(), request as _, PERF_IOC_FLAG_GROUP) } {
+			0 => Ok(()),
+			_ => Err(io::Error::last_os_error()),
+		}
+	}
+
+	impl Counters {
+		/// Open the counters and start counting.
+		pub fn start() -> io::Result<Self> {
+			let leader = open(PERF_COUNT_HW_CACHE_MISSES, -1)?;
+			let branch_misses = open(PERF_COUNT_HW_BRANCH_MISSES, leader.as_raw_fd())?;
+			ioctl(&leader, PERF_EVENT_IOC_ENABLE)?;
+			Ok(Self {
+				leader,
+				_branch_misses: branch_misses,
+			})
+		}
+
+		/// Stop counting and read the counts.
+		pub fn stop(mut self) -> io::Result<Counts> {
+			ioctl(&self.leader, PERF_EVENT_IOC_DISABLE)?;
+			// The number of counters followed by the value of each.
+			let mut buf = [0; 24];
+			self.leader.read_exact(&mut buf)?;
+			let value = |i: usize| u64::from_ne_bytes(buf[i * 8..][..8].try_into().unwrap());
+			Ok(Counts {
+				cache_misses: value(1),
+				branch_misses: value(2),
+			})
+		}
+	}
+}
+
+#[cfg(all(feature = "perf", target_os = "linux"))]
+pub use counters::Counters;
+
+/// Counters for a run, which are only opened if [`Config::perf`] is set.
+pub(crate) struct Run {
+	#[cfg(all(feature = "perf", target_os = "linux"))]
+	counters: Option<Counters>,
+}
+
+impl Run {
+	pub(crate) fn start(cfg: &Config) -> Self {
+		#[cfg(all(feature = "perf", target_os = "linux"))]
+		{
+			let counters = cfg.perf.then(Counters::start).and_then(|c| {
+				c.map_err(|e| eprintln!("perf counters are unavailable: {}", e))
+					.ok()
+			});
+			Self { counters }
+		}
+		#[cfg(not(all(feature = "perf", target_os = "linux")))]
+		{
+			let _ = cfg;
+			Self {}
+		}
+	}
+
+	/// Stop the counters and write the counts to stderr.
+	pub(crate) fn report(self) {
+		#[cfg(all(feature = "perf", target_os = "linux"))]
+		if let Some(c) = self.counters {
+			match c.stop() {
+				Ok(counts) => eprintln!("{}", counts),
+				Err(e) => eprintln!("failed to read perf counters: {}", e),
+			}
+		}
+	}
+}
@@ -0,0 +1,111 @@
+//! Minimal PNG writer for rendering the tape as an image.
+//!
+//! The image data is stored without compression, which keeps the writer small.
+
+/// How cells map to pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+	/// The low 8 bits of a cell are its brightness.
+	Gray,
+	/// The low 24 bits of a cell are its color as `0xRRGGBB`.
+	Rgb,
+}
+
+/// Largest amount of data in a stored deflate block.
+const BLOCK_SIZE: usize = 0xffff;
+
+/// Encode the cells as a PNG image with `width` pixels per row, starting at the top left.
+///
+/// Returns `None` if there are fewer than `width * height` cells or the image is empty.
+///
+/// ```
+/// use dynasm_experiments::png::{image, Color};
+///
+/// let png = image(&[0, 0x80, 0x1ff, 0x3ff], 2, 2, Color::Gray).unwrap();
+/// assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+/// // The width and height are at the start of the IHDR chunk.
+/// assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 2]);
+/// // Each row starts with a filter byte. Stored data follows the zlib and block headers.
+/// let idat = 8 + 25 + 8 + 2 + 5;
+/// assert_eq!(png[idat..idat + 6], [0, 0, 0x80, 0, 0xff, 0xff]);
+///
+/// let png = image(&[0x123456], 1, 1, Color::Rgb).unwrap();
+/// assert_eq!(png[idat..idat + 4], [0, 0x12, 0x34, 0x56]);
+///
+/// assert!(image(&[0; 3], 2, 2, Color::Gray).is_none());
+/// ```
+pub fn image(cells: &[isize], width: u32, height: u32, color: Color) -> Option<Vec<u8>> {
+	let (w, h) = (width as usize, height as usize);
+	let cells = cells.get(..w.checked_mul(h).filter(|&n| n > 0)?)?;
+	let mut raw = Vec::new();
+	for row in cells.chunks(w) {
+		raw.push(0);
+		for &c in row {
+			match color {
+				Color::Gray => raw.push(c as u8),
+				Color::Rgb => raw.extend_from_slice(&(c as u32).to_be_bytes()[1..]),
+			}
+		}
+	}
+
+	let mut ihdr = Vec::new();
+	ihdr.extend_from_slice(&width.to_be_bytes());
+	ihdr.extend_from_slice(&height.to_be_bytes());
+	let color_type = match color {
+		Color::Gray => 0,
+		Color::Rgb => 2,
+	};
+	// Bit depth, color type, compression, filter and interlace method.
+	ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+	let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+	chunk(&mut png, b"IHDR", &ihdr);
+	chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+	chunk(&mut png, b"IEND", &[]);
+	Some(png)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+	png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+	let start = png.len();
+	png.extend_from_slice(kind);
+	png.extend_from_slice(data);
+	let crc = crc32(&png[start..]);
+	png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wrap data in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+	// Deflate with a 32K window and no preset dictionary.
+	let mut out = vec![0x78, 0x01];
+	let mut blocks = data.chunks(BLOCK_SIZE).peekable();
+	while let Some(block) = blocks.next() {
+		out.push(u8::from(blocks.peek().is_none()));
+		let len = block.len() as u16;
+		out.extend_from_slice(&len.to_le_bytes());
+		out.extend_from_slice(&(!len).to_le_bytes());
+		out.extend_from_slice(block);
+	}
+	out.extend_from_slice(&adler32(data).to_be_bytes());
+	out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &b in data {
+		crc ^= u32::from(b);
+		for _ in 0..8 {
+			crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+		}
+	}
+	!crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+	let (mut a, mut b) = (1u32, 0u32);
+	for &d in data {
+		a = (a + u32::from(d)) % 65521;
+		b = (b + a) % 65521;
+	}
+	(b << 16) | a
+}
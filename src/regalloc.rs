@@ -0,0 +1,161 @@
+//! Strategies for choosing which register [`jit2`](crate::jit2) evicts to cache a cell.
+
+#[cfg(feature = "jit")]
+use crate::{gen::Rng, Op};
+#[cfg(feature = "jit")]
+use dynasmrt::x64::Rq;
+#[cfg(feature = "jit")]
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+/// A register allocation strategy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alloc {
+	/// Evict the register that was loaded first.
+	Fifo,
+	/// Evict the register that was used least recently.
+	Lru,
+	/// Evict the register holding the cell the program uses least often.
+	Freq,
+}
+
+impl Alloc {
+	pub const ALL: [Self; 3] = [Self::Fifo, Self::Lru, Self::Freq];
+}
+
+impl fmt::Display for Alloc {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Fifo => "fifo",
+			Self::Lru => "lru",
+			Self::Freq => "freq",
+		})
+	}
+}
+
+impl FromStr for Alloc {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|a| a.to_string() == s)
+			.ok_or("allocator must be fifo, lru or freq")
+	}
+}
+
+/// Decides which register to evict.
+#[cfg(feature = "jit")]
+pub(crate) trait Allocator {
+	/// The register holding a cell was used.
+	fn used(&mut self, r: Rq);
+
+	/// Pick a register to load a cell into. `cached` maps each register in use to its cell.
+	fn pick(&mut self, cached: &HashMap<Rq, isize>) -> Rq;
+}
+
+/// Registers are kept in order of when they were loaded.
+#[cfg(feature = "jit")]
+struct Fifo(VecDeque<Rq>);
+
+#[cfg(feature = "jit")]
+impl Allocator for Fifo {
+	fn used(&mut self, _: Rq) {}
+
+	fn pick(&mut self, _: &HashMap<Rq, isize>) -> Rq {
+		let r = self.0.pop_front().unwrap();
+		self.0.push_back(r);
+		r
+	}
+}
+
+/// Registers are kept in order of when they were used.
+#[cfg(feature = "jit")]
+struct Lru(VecDeque<Rq>);
+
+#[cfg(feature = "jit")]
+impl Allocator for Lru {
+	fn used(&mut self, r: Rq) {
+		let i = self.0.iter().position(|&q| q == r).unwrap();
+		self.0.remove(i);
+		self.0.push_back(r);
+	}
+
+	fn pick(&mut self, _: &HashMap<Rq, isize>) -> Rq {
+		let r = self.0.pop_front().unwrap();
+		self.0.push_back(r);
+		r
+	}
+}
+
+/// How often each cell appears in the program.
+#[cfg(feature = "jit")]
+struct Freq {
+	regs: Vec<Rq>,
+	uses: HashMap<isize, usize>,
+	/// Breaks ties between registers whose cells are used equally often, instead of taking
+	/// the first.
+	rng: Option<Rng>,
+}
+
+#[cfg(feature = "jit")]
+impl Allocator for Freq {
+	fn used(&mut self, _: Rq) {}
+
+	fn pick(&mut self, cached: &HashMap<Rq, isize>) -> Rq {
+		if let Some(&r) = self.regs.iter().find(|r| !cached.contains_key(r)) {
+			return r;
+		}
+		let uses = |r: &Rq| self.uses.get(&cached[r]).copied().unwrap_or(0);
+		let min = self.regs.iter().map(uses).min().unwrap();
+		let least = self.regs.iter().copied().filter(|r| uses(r) == min);
+		let least = least.collect::<Vec<_>>();
+		let i = self
+			.rng
+			.as_mut()
+			.map_or(0, |rng| rng.below(least.len() as u64));
+		least[i as usize]
+	}
+}
+
+/// Create an allocator for the given registers.
+///
+/// With a seed, the registers are shuffled and ties are broken randomly, so different seeds
+/// give different but reproducible allocations.
+#[cfg(feature = "jit")]
+pub(crate) fn allocator(
+	alloc: Alloc,
+	regs: &[Rq],
+	ops: &[Op],
+	seed: Option<u64>,
+) -> Box<dyn Allocator> {
+	let mut rng = seed.map(Rng::new);
+	let mut regs = regs.to_vec();
+	if let Some(rng) = rng.as_mut() {
+		for i in (1..regs.len()).rev() {
+			regs.swap(i, rng.below(i as u64 + 1) as usize);
+		}
+	}
+	match alloc {
+		Alloc::Fifo => Box::new(Fifo(regs.into())),
+		Alloc::Lru => Box::new(Lru(regs.into())),
+		Alloc::Freq => {
+			let mut uses = HashMap::new();
+			for op in ops {
+				let cells = match *op {
+					Op::SetV(a, _) => [Some(a), None],
+					Op::SetA(a, b) => [Some(a), Some(b)],
+					Op::JmpA(_, b) => [Some(b), None],
+					Op::ReadInts(a) => [Some(a), None],
+					Op::Swap(a, b) => [Some(a), Some(b)],
+					Op::JmpV(..) | Op::Call(_) | Op::Ret => [None, None],
+				};
+				for c in cells.into_iter().flatten() {
+					*uses.entry(c).or_insert(0) += 1;
+				}
+			}
+			Box::new(Freq { regs, uses, rng })
+		}
+	}
+}
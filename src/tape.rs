@@ -0,0 +1,166 @@
+//! Tapes for compiled code, which doesn't check its accesses.
+
+/// A tape, optionally surrounded by pages that can't be accessed.
+pub(crate) enum Tape {
+	Plain(Vec<isize>),
+	#[cfg(unix)]
+	Guarded(guard::Guarded),
+}
+
+impl Tape {
+	/// Allocate a tape of `len` cells, of which the first are set to `data`.
+	///
+	/// If `guard_pages` is set and supported, accessing the cells right after the tape or a
+	/// page before it prints the address and exits the process.
+	pub(crate) fn new(len: usize, guard_pages: bool, data: &[isize]) -> Self {
+		let mut tape = match guard_pages {
+			#[cfg(unix)]
+			true => Self::Guarded(guard::Guarded::new(len)),
+			_ => Self::Plain(vec![0; len]),
+		};
+		let data = &data[..data.len().min(len)];
+		// SAFETY: the tape has at least as many cells as are copied.
+		unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), tape.as_mut_ptr(), data.len()) };
+		tape
+	}
+
+	/// The first `len` cells, which must not be more than the tape was allocated with.
+	pub(crate) fn cells(&mut self, len: usize) -> &[isize] {
+		// SAFETY: all tapes have at least len cells from cell 0 on.
+		unsafe { std::slice::from_raw_parts(self.as_mut_ptr(), len) }
+	}
+
+	/// A pointer to cell 0.
+	pub(crate) fn as_mut_ptr(&mut self) -> *mut isize {
+		match self {
+			Self::Plain(v) => v.as_mut_ptr(),
+			#[cfg(unix)]
+			Self::Guarded(g) => g.cells,
+		}
+	}
+}
+
+#[cfg(unix)]
+mod guard {
+	use std::ptr;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Once;
+
+	/// The address of cell 0 and the range of the mapping of the last guarded tape, for the
+	/// signal handler.
+	static CELLS: AtomicUsize = AtomicUsize::new(0);
+	static MAP_START: AtomicUsize = AtomicUsize::new(0);
+	static MAP_END: AtomicUsize = AtomicUsize::new(0);
+
+	/// A tape in the middle of a mapping which starts and ends with a page that can't be
+	/// accessed.
+	///
+	/// The end of the tape is aligned to the trailing page so overruns fault immediately.
+	/// Accesses to negative addresses may not fault until they are beyond the padding before
+	/// cell 0.
+	pub(crate) struct Guarded {
+		map: *mut libc::c_void,
+		map_len: usize,
+		pub(crate) cells: *mut isize,
+	}
+
+	impl Guarded {
+		pub(crate) fn new(len: usize) -> Self {
+			static HANDLER: Once = Once::new();
+			HANDLER.call_once(install_handler);
+
+			// SAFETY: sysconf has no preconditions.
+			let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+			let size = (len * 8 + page - 1) & !(page - 1);
+			let map_len = size + 2 * page;
+			// SAFETY: a new mapping doesn't affect any existing memory.
+			let map = unsafe {
+				libc::mmap(
+					ptr::null_mut(),
+					map_len,
+					libc::PROT_NONE,
+					libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+					-1,
+					0,
+				)
+			};
+			assert_ne!(map, libc::MAP_FAILED, "failed to map tape");
+			// SAFETY: the range is within the mapping.
+			let usable = unsafe { map.cast::<u8>().add(page) };
+			let r =
+				unsafe { libc::mprotect(usable.cast(), size, libc::PROT_READ | libc::PROT_WRITE) };
+			assert_eq!(r, 0, "failed to unprotect tape");
+			let cells = unsafe { usable.add(size - len * 8) }.cast::<isize>();
+			CELLS.store(cells as usize, Ordering::Relaxed);
+			MAP_START.store(map as usize, Ordering::Relaxed);
+			MAP_END.store(map as usize + map_len, Ordering::Relaxed);
+			Self {
+				map,
+				map_len,
+				cells,
+			}
+		}
+	}
+
+	impl Drop for Guarded {
+		fn drop(&mut self) {
+			MAP_START.store(0, Ordering::Relaxed);
+			MAP_END.store(0, Ordering::Relaxed);
+			// SAFETY: nothing refers to the tape anymore.
+			unsafe { libc::munmap(self.map, self.map_len) };
+		}
+	}
+
+	fn install_handler() {
+		// SAFETY: the handler only calls async-signal-safe functions.
+		unsafe {
+			let mut action = std::mem::zeroed::<libc::sigaction>();
+			action.sa_sigaction = handler as *const () as usize;
+			action.sa_flags = libc::SA_SIGINFO;
+			libc::sigaction(libc::SIGSEGV, &action, ptr::null_mut());
+		}
+	}
+
+	/// Report faults in the guard pages and exit, or fault again with the default handler.
+	extern "C" fn handler(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+		// SAFETY: SA_SIGINFO is set so info is valid.
+		let addr = unsafe { (*info).si_addr() } as usize;
+		let range = MAP_START.load(Ordering::Relaxed)..MAP_END.load(Ordering::Relaxed);
+		if !range.contains(&addr) {
+			// SAFETY: returning retries the access, which then crashes as usual.
+			unsafe { libc::signal(libc::SIGSEGV, libc::SIG_DFL) };
+			return;
+		}
+		let cell = (addr as isize - CELLS.load(Ordering::Relaxed) as isize).div_euclid(8);
+
+		// Formatting may allocate, which isn't safe in a signal handler.
+		let mut buf = [0; 64];
+		let msg = b"error: access to cell ";
+		buf[..msg.len()].copy_from_slice(msg);
+		let mut len = msg.len();
+		if cell < 0 {
+			buf[len] = b'-';
+			len += 1;
+		}
+		let mut digits = [0; 20];
+		let (mut n, mut i) = (cell.unsigned_abs(), digits.len());
+		loop {
+			i -= 1;
+			digits[i] = b'0' + (n % 10) as u8;
+			n /= 10;
+			if n == 0 {
+				break;
+			}
+		}
+		buf[len..len + digits.len() - i].copy_from_slice(&digits[i..]);
+		len += digits.len() - i;
+		let msg = b" outside the tape\n";
+		buf[len..len + msg.len()].copy_from_slice(msg);
+		len += msg.len();
+		// SAFETY: write and _exit are async-signal-safe.
+		unsafe {
+			libc::write(2, buf.as_ptr().cast(), len);
+			libc::_exit(1);
+		}
+	}
+}
@@ -0,0 +1,117 @@
+//! Traces of the ops the checked interpreter executes.
+
+use crate::Op;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How a trace is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	/// One line per op for reading.
+	Text,
+	/// One JSON object per line.
+	Json,
+	/// A JSON array of complete events in the Chrome trace event format, which Perfetto and
+	/// `chrome://tracing` can load. Each op takes one microsecond.
+	Chrome,
+}
+
+impl Format {
+	pub const ALL: [Self; 3] = [Self::Text, Self::Json, Self::Chrome];
+
+	/// Render the op at index `ip`, executed as the `step`th op with V being `v` before it.
+	///
+	/// ```
+	/// use dynasm_experiments::{trace::Format, Op};
+	///
+	/// let op = Op::SetV(1, -2);
+	/// assert_eq!(Format::Text.event(3, 7, op, 5), "         3      7  1`+-2  V: 5");
+	/// assert_eq!(
+	///     Format::Json.event(3, 7, op, 5),
+	///     r#"{"step":3,"ip":7,"op":"1`+-2","v":5}"#
+	/// );
+	/// assert_eq!(
+	///     Format::Chrome.event(3, 7, op, 5),
+	///     r#"{"name":"1`+-2","ph":"X","ts":3,"dur":1,"pid":0,"tid":0,"args":{"ip":7,"v":5}}"#
+	/// );
+	/// ```
+	pub fn event(self, step: u64, ip: usize, op: Op, v: isize) -> String {
+		// Ops never contain characters that need escaping in JSON strings.
+		match self {
+			Self::Text => format!("{:>10} {:>6}  {}  V: {}", step, ip, op, v),
+			Self::Json => format!(r#"{{"step":{},"ip":{},"op":"{}","v":{}}}"#, step, ip, op, v),
+			Self::Chrome => format!(
+				r#"{{"name":"{}","ph":"X","ts":{},"dur":1,"pid":0,"tid":0,"args":{{"ip":{},"v":{}}}}}"#,
+				op, step, ip, v
+			),
+		}
+	}
+}
+
+impl fmt::Display for Format {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Text => "text",
+			Self::Json => "json",
+			Self::Chrome => "chrome",
+		})
+	}
+}
+
+impl FromStr for Format {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|t| t.to_string() == s)
+			.ok_or("trace format must be text, json or chrome")
+	}
+}
+
+/// Writes events to a trace file and finishes it when dropped.
+pub(crate) struct Tracer {
+	format: Format,
+	out: BufWriter<File>,
+	events: u64,
+}
+
+impl Tracer {
+	pub(crate) fn new(path: &Path, format: Format) -> io::Result<Self> {
+		let mut out = BufWriter::new(File::create(path)?);
+		if format == Format::Chrome {
+			out.write_all(b"[\n")?;
+		}
+		Ok(Self {
+			format,
+			out,
+			events: 0,
+		})
+	}
+
+	pub(crate) fn event(&mut self, ip: usize, op: Op, v: isize) {
+		let sep = if self.format == Format::Chrome && self.events > 0 {
+			",\n"
+		} else {
+			""
+		};
+		let e = self.format.event(self.events, ip, op, v);
+		// A trace that can't be written is not worth stopping the program for.
+		let _ = match self.format {
+			Format::Chrome => write!(self.out, "{}{}", sep, e),
+			_ => writeln!(self.out, "{}", e),
+		};
+		self.events += 1;
+	}
+}
+
+impl Drop for Tracer {
+	fn drop(&mut self) {
+		if self.format == Format::Chrome {
+			let _ = self.out.write_all(b"\n]\n");
+		}
+	}
+}
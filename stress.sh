@@ -0,0 +1,34 @@
+#!/usr/bin/env bash
+# Generate large programs with gen --stress and check that both JITs give the same output as
+# the interpreter, also when continuing halfway from the interpreter's state. The programs
+# are large enough that jumps, calls and cell displacements need 32-bit encodings. Each seed
+# is also generated with --no-calls, which jit2 compiles with cells kept in registers, and
+# jit2 runs with one and two registers as well.
+# $1 is the amount of ops (default 200000), $2 the amount of seeds to try (default 3).
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+len=${1:-200000}
+status=0
+modes=(checked jit jit2 "--regs 1 jit2" "--regs 2 jit2")
+for seed in $(seq 1 "${2:-3}"); do
+	for variant in "" --no-calls; do
+		name="seed $seed${variant:+ $variant}"
+		$bin --seed "$seed" --stress $variant gen "$len" > /tmp/stress.ft
+		expect=$($bin --output decimal interpreter /tmp/stress.ft 2>/dev/null)
+		for mode in "${modes[@]}"; do
+			if [ "$($bin --output decimal $mode /tmp/stress.ft 2>/dev/null)" == "$expect" ]; then
+				echo "ok   $name $mode"
+			else
+				echo "FAIL $name $mode"
+				status=1
+			fi
+		done
+		if $bin handoff /tmp/stress.ft > /dev/null 2>&1; then
+			echo "ok   $name handoff"
+		else
+			echo "FAIL $name handoff"
+			status=1
+		fi
+	done
+done
+exit $status
@@ -0,0 +1,31 @@
+#!/usr/bin/env bash
+# Trace each example in every JSON format and check that the traces are well-formed with
+# Python's json module. json traces must have one object per line and chrome traces must be
+# a single array.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+status=0
+for f in examples/*.ft; do
+	input=${f%.ft}.in
+	[ -f "$input" ] || input=/dev/null
+	args=$(cat "${f%.ft}.args" 2>/dev/null)
+	for format in json chrome; do
+		$bin $args --trace /tmp/trace.json --trace-format $format checked "$f" < "$input" > /dev/null 2>&1
+		if python3 -c '
+import json, sys
+fmt, text = sys.argv[1], open("/tmp/trace.json").read()
+if fmt == "json":
+    events = [json.loads(l) for l in text.splitlines()]
+else:
+    events = json.loads(text)
+    assert isinstance(events, list)
+assert events
+' $format; then
+			echo "ok   $f $format"
+		else
+			echo "FAIL $f $format"
+			status=1
+		fi
+	done
+done
+exit $status
@@ -0,0 +1,50 @@
+#!/usr/bin/env bash
+# Check that --watch runs a program again after each save, also after a save with a syntax
+# error, which must not end the watch.
+cargo b --release || exit $?
+bin=./target/release/dynasm_experiments
+src=/tmp/watch.ft
+out=/tmp/watch.out
+err=/tmp/watch.err
+status=0
+printf '0`+65\n' > $src
+$bin --watch --strict interpreter $src > $out 2> $err &
+pid=$!
+# Wait up to 5 seconds for the output to be $1.
+expect() {
+	for _ in $(seq 50); do
+		[ "$(cat $out)" == "$1" ] && return 0
+		sleep 0.1
+	done
+	return 1
+}
+step() {
+	if expect "$2"; then
+		echo "ok   $1"
+	else
+		echo "FAIL $1: $(cat $out)"
+		status=1
+	fi
+}
+step "first run" A
+sleep 0.2
+printf '0`+66\n' > $src
+step "run after a save" AB
+sleep 0.2
+printf '0`+ `\n' > $src
+for _ in $(seq 50); do
+	grep -q "error: " $err && break
+	sleep 0.1
+done
+if grep -q "error: " $err; then
+	echo "ok   syntax error reported"
+else
+	echo "FAIL syntax error not reported"
+	status=1
+fi
+sleep 0.2
+printf '0`+67\n' > $src
+step "run after a syntax error" ABC
+kill $pid
+wait $pid 2>/dev/null
+exit $status